@@ -1,80 +1,174 @@
 use crate::config::Config;
-// import serde for derive magic
-use duct::cmd;
-use std::collections::HashMap;
+use crate::links::{self, Link};
+use crate::metadata;
+use crate::notes_map::{self, NotesMap};
+use crate::output::{self, Format, Record};
+use serde::Serialize;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
-pub fn run(config: Config, file: &PathBuf, absolute: bool, nested: bool, verbose: bool) {
+/// A `Record` for a backlink, `path` is the linking note
+#[derive(Debug, Serialize)]
+struct BacklinkRecord {
+    #[serde(flatten)]
+    record: Record,
+    /// The heading or block the link points at
+    anchor: Option<String>,
+    /// The link's text or alias
+    text: Option<String>,
+    /// The lines around the link, with `--context`
+    context: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+pub struct BacklinkOptions {
+    /// Only links to this heading or `^block`
+    pub heading: Option<String>,
+    /// Print each link with this many lines around it, rather than the notes
+    pub context: Option<usize>,
+    pub absolute: bool,
+    pub nested: bool,
+}
+
+/// List the notes linking to `file`, or each link with its surrounding lines
+pub fn run(config: Config, file: &PathBuf, opts: &BacklinkOptions, verbose: bool, format: Format) {
     // get the absolute path of the file
     let target = file
         .canonicalize()
         .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", file));
 
-    // Get the directories under the slipbox
-    // For each directory the backlinks will be relative
-    let notes_dir = Path::new(&config.note_taking_dir);
-    let dirs = get_dirs(notes_dir);
-
-    let backlinks = match nested {
-        true => dirs
-            .into_iter()
-            .flat_map(|d| {
-                // Get the relative path
-                let relpath = relpath(&Path::new(&d), &target);
-                grep(&Path::new(&d), &Path::new(&relpath), verbose)
-            })
-            .collect(),
-        false => {
-            // Get the relative path
-            let relpath = relpath(notes_dir, &target);
-
-            grep(notes_dir, &Path::new(&relpath), verbose)
+    let (notes_dir, map) = notes_map::load(&config, opts.nested, verbose);
+
+    let heading = opts.heading.as_deref();
+    if let (Some(heading), Some(note)) = (heading, map.get(&target)) {
+        if !links::anchor_matches(heading, &note.anchors) {
+            eprintln!("No heading or block {heading:?} in {}", file.display());
         }
-    };
-
-    // Print the backlinks (assuming they are relative to this dir)
-    for b in backlinks {
-        if absolute {
-            println!("{b}");
-        } else {
-            let mut relpath = b.replace(&config.note_taking_dir, "");
-            // drop the leading slash
-            if relpath.starts_with("/") {
-                relpath = relpath[1..].to_string();
+    }
+
+    // Print the backlinks (relative to the notes directory unless absolute)
+    let mut records = vec![];
+    let mut seen = vec![];
+    let mut printed = 0;
+    let mut last = LastNote::default();
+    for (source, link) in links_to(&map, &target, heading) {
+        // One line per note and one record per note and anchor, unless each
+        // link is shown in its context
+        if opts.context.is_none() {
+            let anchor = match format {
+                Format::Plain => None,
+                _ => link.anchor.as_deref(),
+            };
+            if seen.contains(&(source, anchor)) {
+                continue;
             }
-            println!("{relpath}");
+            seen.push((source, anchor));
         }
+        let shown = display_path(&notes_dir, source, opts.absolute);
+        if format == Format::Plain && opts.context.is_none() {
+            println!("{shown}");
+            continue;
+        }
+        let content = last.read(source);
+        let lines: Vec<&str> = content.lines().collect();
+        let around = opts.context.map(|n| context(&lines, link.line, n));
+        if format == Format::Plain {
+            if printed > 0 {
+                println!();
+            }
+            printed += 1;
+            print_context(&shown, link, &around.unwrap_or_default());
+            continue;
+        }
+        let mut record = Record::new(shown);
+        record.title = metadata::parse(content).title;
+        record.snippet = lines.get(link.line - 1).map(|l| l.to_string());
+        record.line = Some(link.line);
+        records.push(BacklinkRecord {
+            record,
+            anchor: link.anchor.clone(),
+            text: link.text.clone(),
+            context: around.map(|a| a.into_iter().map(|(_, l)| l.to_string()).collect()),
+        });
+    }
+    output::print_json(&records, format);
+}
+
+/// The numbered lines from `n` before to `n` after the 1-based `line`
+fn context<'a>(lines: &[&'a str], line: usize, n: usize) -> Vec<(usize, &'a str)> {
+    let start = line.saturating_sub(n).max(1);
+    let end = (line + n).min(lines.len());
+    (start..=end).map(|i| (i, lines[i - 1])).collect()
+}
+
+/// `file:line: text` then the lines, marked like grep with `:` for the link
+/// and `-` for the context
+fn print_context(shown: &str, link: &Link, lines: &[(usize, &str)]) {
+    let text = link.text.clone().unwrap_or_else(|| {
+        let anchor = link.anchor.as_ref().map(|a| format!("#{a}"));
+        format!("{}{}", link.target, anchor.unwrap_or_default())
+    });
+    println!("{shown}:{}: {text}", link.line);
+    let width = lines.last().map_or(1, |(i, _)| i.to_string().len());
+    for (i, line) in lines {
+        let mark = if *i == link.line { ':' } else { '-' };
+        println!("{i:>width$}{mark} {line}");
+    }
+}
+
+/// The links to `target`, or with `heading` only those to that heading or block
+fn links_to<'a>(
+    map: &'a NotesMap,
+    target: &Path,
+    heading: Option<&str>,
+) -> Vec<(&'a Path, &'a Link)> {
+    let mut backlinks = map.backlinks(target);
+    if let Some(heading) = heading {
+        let heading = [heading.to_string()];
+        backlinks.retain(|(_, link)| {
+            link.anchor
+                .as_ref()
+                .is_some_and(|a| links::anchor_matches(a, &heading))
+        });
+    }
+    backlinks
+}
+
+/// The last note read. Links are grouped by note, so each note is only read
+/// once while going through them.
+#[derive(Debug, Default)]
+pub struct LastNote {
+    path: PathBuf,
+    content: String,
+}
+
+impl LastNote {
+    /// The content of `path`, empty if it can't be read
+    pub fn read(&mut self, path: &Path) -> &str {
+        if self.path != path {
+            self.content = fs::read_to_string(path).unwrap_or_default();
+            self.path = path.to_path_buf();
+        }
+        &self.content
+    }
+}
+
+/// `path` as shown to the user, relative to the notes directory unless `absolute`
+pub fn display_path(notes_dir: &Path, path: &Path, absolute: bool) -> String {
+    match absolute {
+        true => path.display().to_string(),
+        false => relpath(notes_dir, path),
     }
 }
 
 /// Returns the relative path if possible
 /// If not possible returns the absolute path
 /// Input should be absolute path.
-fn relpath(dir: &Path, target: &Path) -> String {
+pub fn relpath(dir: &Path, target: &Path) -> String {
     if let Ok(dir) = dir.canonicalize() {
         if let Ok(target) = target.canonicalize() {
-            let dir_components = dir.components().collect::<Vec<_>>();
-            let tgt_components = target.components().collect::<Vec<_>>();
-
-            let common_length = dir_components
-                .iter()
-                .zip(tgt_components.iter())
-                .take_while(|&(d, t)| d == t)
-                .count();
-
-            let mut relative_path = std::iter::repeat("..")
-                .take(dir_components.len() - common_length)
-                .collect::<Vec<_>>();
-
-            relative_path.extend(
-                tgt_components[common_length..]
-                    .iter()
-                    .map(|c| c.as_os_str().to_str().unwrap()),
-            );
-
-            return relative_path.join("/");
+            return relative_path(&dir, &target);
         }
 
         return dir
@@ -87,122 +181,89 @@ fn relpath(dir: &Path, target: &Path) -> String {
         .to_string()
 }
 
-fn get_dirs(dir: &Path) -> Vec<String> {
-    // unfortunately grep returns 1 on no files found
-    // so we'll have to treat failure as no files found
-    if let Ok(stduot) = cmd!("fd", "-t", "d", ".", dir).read() {
-        let mut out: Vec<String> = stduot.lines().map(|x| x.to_string()).collect();
-        let dir = dir.canonicalize().expect("Unable to get abs path");
-        out.push(dir.to_str().unwrap().to_string());
-        out
-    } else {
-        eprintln!("Non Zero Exit using fd on {:?}", dir);
-        vec![]
-    }
-}
+/// The path from `dir` to `target` without touching the file system, so either
+/// may not exist yet. Both should be absolute and normalized.
+pub fn relative_path(dir: &Path, target: &Path) -> String {
+    let dir_components = dir.components().collect::<Vec<_>>();
+    let tgt_components = target.components().collect::<Vec<_>>();
 
-fn grep(dir: &Path, query: &Path, verbose: bool) -> Vec<String> {
-    // unfortunately grep returns 1 on no files found
-    // so we'll have to treat failure as no files found
-    match cmd!("rg", "-l", query, dir).read() {
-        Ok(stdout) => stdout.lines().map(|x| x.to_string()).collect(),
-        Err(_) => {
-            if verbose {
-                eprintln!("Non Zero Exit using rg on {:?}", dir);
-            }
-            vec![]
-        }
-    }
-}
+    let common_length = dir_components
+        .iter()
+        .zip(tgt_components.iter())
+        .take_while(|&(d, t)| d == t)
+        .count();
 
-// Allow dead code in the event that we want to avoid shelling out
-// ripgrep and fd are very fast and efficient, so this is unlikely
-#[allow(dead_code)]
-fn get_dirs_native(parent_directory: &Path, verbose: bool) -> Vec<String> {
-    let dirs = walk(&parent_directory, verbose);
-    dirs.keys().map(|x| x.to_string()).collect()
-}
+    let mut relative_path =
+        std::iter::repeat_n("..", dir_components.len() - common_length).collect::<Vec<_>>();
 
-fn walk(filepath: &Path, verbose: bool) -> HashMap<String, Vec<String>> {
-    let mut file_map = HashMap::new();
-    walk_dir_get_dirs(&filepath, &mut file_map, verbose);
-    for (path, _content) in &file_map {
-        if verbose {
-            println!("Path: {:?}", path);
-        }
-    }
-    file_map
+    relative_path.extend(
+        tgt_components[common_length..]
+            .iter()
+            .map(|c| c.as_os_str().to_str().unwrap()),
+    );
+
+    relative_path.join("/")
 }
 
-fn walk_dir_get_dirs(filepath: &Path, file_map: &mut HashMap<String, Vec<String>>, verbose: bool) {
-    if filepath.is_dir() {
-        // ignore hidden directories
-        if filepath
-            .file_name()
-            .unwrap_or_else(|| panic!("Unable to get Filename from {:?}", filepath))
-            .to_str()
-            .unwrap_or_else(|| panic!("Unable to get string from {:?}", filepath))
-            .starts_with(".")
-        {
-            return;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Ok(entries) = fs::read_dir(filepath) {
-            for entry in entries {
-                if let Ok(e) = entry {
-                    let path = e.path();
-                    walk_dir_get_dirs(&path, file_map, verbose);
-                }
-            }
-        }
-    } else {
-        // In here filepath is known to be a file not a dir
-        // Try to get a string
-        if let Some(path_str) = filepath.to_str() {
-            // Try to get parent dir of this file
-            if let Some(parent_dir) = filepath.parent() {
-                // Try to get that as a string
-                if let Some(parent_dir) = parent_dir.to_str() {
-                    // Store it in the map
-                    file_map
-                        // Try to get the vector out
-                        .entry(parent_dir.to_owned())
-                        // Otherwise add an empty vector
-                        .or_insert(vec![])
-                        // Push this string into that vector
-                        .push(path_str.to_owned());
-                }
-            }
-        }
+    #[test]
+    fn backlinks_resolve_relative_to_nested_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("target.md"), "# Target").unwrap();
+        std::fs::write(root.join("flat.md"), "[t](target.md)").unwrap();
+        std::fs::write(root.join("wiki.md"), "[[Target#Section]]").unwrap();
+        std::fs::write(root.join("mention.md"), "target.md is a plain mention").unwrap();
+        std::fs::write(root.join("sub/nested.md"), "\n[t](../target)").unwrap();
+
+        let sources = |nested| -> Vec<String> {
+            let map = NotesMap::load(&root, &cache.path().join("links.json"), nested, false);
+            map.backlinks(&root.join("target.md"))
+                .into_iter()
+                .map(|(p, _)| relpath(&root, p))
+                .collect()
+        };
+        assert_eq!(sources(false), vec!["flat.md", "wiki.md"]);
+        assert_eq!(sources(true), vec!["flat.md", "sub/nested.md", "wiki.md"]);
     }
-}
 
-#[allow(dead_code)]
-fn grep_native(dir: &Path, query: &str, verbose: bool) -> Vec<String> {
-    let mut results = Vec::new();
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Ok(file_type) = entry.file_type() {
-                    if file_type.is_file() {
-                        let file_path = entry.path();
-
-                        // check if query is in content
-                        if let Ok(content) = std::fs::read_to_string(&file_path) {
-                            if content.contains(query) {
-                                results.push(file_path.to_str().unwrap().to_string());
-                            }
-                        } else if verbose {
-                            eprintln!("Failed to read {:?}", file_path);
-                        }
-                    }
-                }
-            } else if verbose {
-                eprintln!("Failed to process an entry in {:?}", dir);
-            }
-        }
-    } else if verbose {
-        eprintln!("Failed to open {:?}", dir);
+    #[test]
+    fn context_is_clamped_to_the_note() {
+        let lines = ["a", "b", "c", "d"];
+        assert_eq!(context(&lines, 1, 1), vec![(1, "a"), (2, "b")]);
+        assert_eq!(context(&lines, 3, 0), vec![(3, "c")]);
+        assert_eq!(context(&lines, 4, 5).len(), 4);
+    }
+
+    #[test]
+    fn backlinks_to_a_heading() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("target.md"), "# Set Up\n# Usage").unwrap();
+        std::fs::write(root.join("a.md"), "[[target#set up]] [t](target.md#usage)").unwrap();
+        std::fs::write(root.join("b.md"), "[t](target.md#Usage)").unwrap();
+        std::fs::write(root.join("c.md"), "[t](target.md)").unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+        let sources = |heading| -> Vec<(String, Option<String>)> {
+            links_to(&map, &root.join("target.md"), heading)
+                .into_iter()
+                .map(|(p, l)| (relpath(&root, p), l.anchor.clone()))
+                .collect()
+        };
+        assert_eq!(
+            sources(Some("Usage")),
+            vec![
+                ("a.md".to_string(), Some("usage".to_string())),
+                ("b.md".to_string(), Some("Usage".to_string())),
+            ]
+        );
+        assert_eq!(sources(None).len(), 4);
     }
-    results
 }
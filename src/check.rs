@@ -0,0 +1,115 @@
+use crate::backlinks::{display_path, LastNote};
+use crate::config::Config;
+use crate::notes_map::{self, LinkStatus, NotesMap, ResolvedLink};
+use crate::output::{self, Format, Record};
+use serde::Serialize;
+use std::path::Path;
+
+/// A `Record` for a broken link, `path` is the note containing it
+#[derive(Debug, Serialize)]
+struct BrokenLink {
+    #[serde(flatten)]
+    record: Record,
+    target: String,
+    anchor: Option<String>,
+    /// `missing` or `missing-anchor`
+    status: LinkStatus,
+}
+
+/// Report every internal link that doesn't resolve to a note, or to a heading
+/// or block in it.
+/// Returns whether the vault is free of broken links.
+pub fn links(config: Config, absolute: bool, nested: bool, verbose: bool, format: Format) -> bool {
+    let (notes_dir, map) = notes_map::load(&config, nested, verbose);
+
+    let broken = broken_links(&map);
+    let mut records = vec![];
+    let mut last = LastNote::default();
+    for (source, resolved) in &broken {
+        let link = &resolved.link;
+        let shown = display_path(&notes_dir, source, absolute);
+        if format == Format::Plain {
+            let anchor = match resolved.status() {
+                LinkStatus::MissingAnchor => link.anchor.as_ref().map(|a| format!("#{a}")),
+                _ => None,
+            };
+            println!(
+                "{shown}:{}: {}{}",
+                link.line,
+                link.target,
+                anchor.unwrap_or_default()
+            );
+            continue;
+        }
+        let mut record = Record::new(shown);
+        record.snippet = last
+            .read(source)
+            .lines()
+            .nth(link.line - 1)
+            .map(str::to_string);
+        record.line = Some(link.line);
+        records.push(BrokenLink {
+            record,
+            target: link.target.clone(),
+            anchor: link.anchor.clone(),
+            status: resolved.status(),
+        });
+    }
+    output::print_json(&records, format);
+
+    if !broken.is_empty() {
+        let mut notes: Vec<&Path> = broken.iter().map(|(s, _)| *s).collect();
+        notes.dedup();
+        eprintln!("{} broken links in {} notes", broken.len(), notes.len());
+    }
+    broken.is_empty()
+}
+
+/// Internal links that don't resolve to a note or anchor, in path and line order
+fn broken_links(map: &NotesMap) -> Vec<(&Path, &ResolvedLink)> {
+    map.notes()
+        .flat_map(|(source, note)| note.links.iter().map(move |l| (source, l)))
+        .filter(|(_, l)| matches!(l.status(), LinkStatus::Missing | LinkStatus::MissingAnchor))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlinks::relpath;
+
+    #[test]
+    fn broken_links_honor_nested_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.md"), "[b](sub/b.md) [[b]]\n[x](x.md)").unwrap();
+        std::fs::write(
+            root.join("sub/b.md"),
+            "[a](../a.md) https://a.b [u](https://a.b)",
+        )
+        .unwrap();
+
+        let broken = |nested| -> Vec<(String, usize, String)> {
+            let map = NotesMap::load(&root, &cache.path().join("links.json"), nested, false);
+            broken_links(&map)
+                .into_iter()
+                .map(|(s, l)| (relpath(&root, s), l.link.line, l.link.target.clone()))
+                .collect()
+        };
+        // Flat: links are relative to the notes directory
+        assert_eq!(
+            broken(false),
+            vec![
+                ("a.md".to_string(), 2, "x.md".to_string()),
+                ("sub/b.md".to_string(), 1, "../a.md".to_string()),
+            ]
+        );
+        // Nested: links are relative to the linking note
+        assert_eq!(
+            broken(true),
+            vec![("a.md".to_string(), 2, "x.md".to_string())]
+        );
+    }
+}
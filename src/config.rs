@@ -1,25 +1,126 @@
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 // A struct for meta info including things like note taking directory, vim path, vscode path, default editor etc.
 // This struct will be used to store the meta info and will be passed around to various functions
+//
+// It is read from `$XDG_CONFIG_HOME/nt/config.toml` (or the `--config` path), any key
+// that is missing falls back to the value in `Config::default()`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub note_taking_dir: String,
     pub vim_path: String,
     pub vscode_path: String,
     pub default_editor: String,
+    /// Weight of the keyword ranking in `search --hybrid`
+    pub hybrid_lexical_weight: f32,
+    /// Weight of the semantic ranking in `search --hybrid`
+    pub hybrid_semantic_weight: f32,
+    /// Vault used when `--vault` is not given
+    pub default_vault: Option<String>,
+    /// Named vaults, e.g. `[vaults.work]`
+    pub vaults: HashMap<String, Vault>,
+    /// How `nt new` names files
+    pub filename_scheme: FilenameScheme,
+    /// Template name or file for `nt new`, a built-in template is used if unset
+    pub new_template: Option<String>,
+    /// Directory of named templates, instead of the vault's `.templates` and
+    /// `$XDG_CONFIG_HOME/nt/templates`
+    pub templates_dir: Option<String>,
+    /// The `author` of new notes, defaults to `$USER`
+    pub author: Option<String>,
+    /// The name of the selected vault, if any
+    #[serde(skip)]
+    pub vault: Option<String>,
 }
 
-impl Config {
-    pub fn default() -> Config {
+/// A named slipbox with its own notes directory and search index
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Vault {
+    pub note_taking_dir: String,
+}
+
+/// File names of new notes, the ID is a Zettelkasten timestamp like `20240131093000`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilenameScheme {
+    /// `my-title.md`
+    #[default]
+    Slug,
+    /// `20240131093000.md`
+    Id,
+    /// `20240131093000-my-title.md`
+    IdSlug,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The config file is not valid TOML or contains unknown keys
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// The note taking directory does not exist
+    MissingNotesDir(PathBuf),
+    /// The requested vault is not defined in the config file
+    UnknownVault(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "Unable to read config file {}: {source}", path.display())
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "Invalid config file {}: {source}", path.display())
+            }
+            ConfigError::MissingNotesDir(dir) => write!(
+                f,
+                "Note taking directory does not exist: {} (set note_taking_dir or NT_NOTES_DIR)",
+                dir.display()
+            ),
+            ConfigError::UnknownVault(name) => {
+                write!(f, "Unknown vault: {name} (define it under [vaults.{name}])")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Default for Config {
+    fn default() -> Config {
         let home = env::var("HOME").expect("HOME not set");
         Config {
             note_taking_dir: format!("{home}/Notes/slipbox"),
             vim_path: "/usr/bin/nvim".to_string(),
             vscode_path: "/usr/bin/codium".to_string(),
             default_editor: "vim".to_string(),
+            hybrid_lexical_weight: 1.0,
+            hybrid_semantic_weight: 1.0,
+            default_vault: None,
+            vaults: HashMap::new(),
+            filename_scheme: FilenameScheme::Slug,
+            new_template: None,
+            templates_dir: None,
+            author: None,
+            vault: None,
         }
     }
+}
 
+impl Config {
     #[allow(dead_code)]
     pub fn new(
         note_taking_dir: String,
@@ -32,14 +133,153 @@ impl Config {
             vim_path,
             vscode_path,
             default_editor,
+            ..Config::default()
+        }
+    }
+
+    /// Load the config from `path`, or from the default location if `None`.
+    ///
+    /// An explicit path must exist, the default location is optional.
+    /// The vault is selected (`vault`, else `NT_VAULT`, else `default_vault`),
+    /// environment variables are applied on top and the result is validated.
+    pub fn load(path: Option<&Path>, vault: Option<&str>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(p) => Config::from_file(p)?,
+            None => match default_path() {
+                Some(p) if p.exists() => Config::from_file(&p)?,
+                _ => Config::default(),
+            },
+        };
+        config.resolve(vault, |k| env::var(k).ok())?;
+        config.note_taking_dir = expand_tilde(&config.note_taking_dir);
+        config.new_template = config.new_template.as_deref().map(expand_tilde);
+        config.templates_dir = config.templates_dir.as_deref().map(expand_tilde);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parse a TOML config file, unknown keys are an error
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Config::from_toml(&content).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn from_toml(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    /// Select the vault, then apply the environment so `NT_NOTES_DIR` still
+    /// wins over the vault's directory, and over the vault itself
+    fn resolve<F: Fn(&str) -> Option<String>>(
+        &mut self,
+        vault: Option<&str>,
+        lookup: F,
+    ) -> Result<(), ConfigError> {
+        let vault = vault
+            .map(str::to_string)
+            .or_else(|| lookup("NT_VAULT"))
+            .or_else(|| self.default_vault.clone());
+        if let Some(name) = vault {
+            self.select_vault(&name)?;
+        }
+        self.apply_env(lookup);
+        Ok(())
+    }
+
+    /// Override fields with any environment variables that are set.
+    /// The lookup is passed in so tests don't have to touch the real environment.
+    fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, lookup: F) {
+        if let Some(dir) = lookup("NT_NOTES_DIR") {
+            // The directory is no longer the vault's, nor is its cache
+            self.note_taking_dir = dir;
+            self.vault = None;
+        }
+        if let Some(vim) = lookup("NT_VIM_PATH") {
+            self.vim_path = vim;
+        }
+        if let Some(vscode) = lookup("NT_VSCODE_PATH") {
+            self.vscode_path = vscode;
         }
+        if let Some(editor) = lookup("NT_EDITOR") {
+            self.default_editor = editor;
+        }
+    }
+
+    /// Point the config at the notes directory of the named vault
+    fn select_vault(&mut self, name: &str) -> Result<(), ConfigError> {
+        let vault = self
+            .vaults
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownVault(name.to_string()))?;
+        self.note_taking_dir = vault.note_taking_dir.clone();
+        self.vault = Some(name.to_string());
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let dir = Path::new(&self.note_taking_dir);
+        if !dir.is_dir() {
+            return Err(ConfigError::MissingNotesDir(dir.to_path_buf()));
+        }
+        Ok(())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/nt/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("nt").join("config.toml"))
+}
+
+fn expand_tilde(path: &str) -> String {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => path.to_string(),
     }
 }
 
-// Test that the note_taking_dir is set correctly
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn missing_keys_fall_back_to_defaults() {
+        let config = Config::from_toml(r#"note_taking_dir = "/tmp/notes""#).unwrap();
+        let default = Config::default();
+
+        assert_eq!(config.note_taking_dir, "/tmp/notes");
+        assert_eq!(config.vim_path, default.vim_path);
+        assert_eq!(config.vscode_path, default.vscode_path);
+        assert_eq!(config.default_editor, default.default_editor);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = Config::from_toml(r#"note_dir = "/tmp/notes""#).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field"),
+            "Unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn env_overrides_file_values() {
+        let mut config = Config::from_toml(r#"default_editor = "vim""#).unwrap();
+        config.apply_env(|k| match k {
+            "NT_NOTES_DIR" => Some("/srv/notes".to_string()),
+            "NT_EDITOR" => Some("codium".to_string()),
+            _ => None,
+        });
+
+        assert_eq!(config.note_taking_dir, "/srv/notes");
+        assert_eq!(config.default_editor, "codium");
+    }
+
     #[test]
     fn note_taking_directory_exists() {
         let config = Config::default();
@@ -60,6 +300,7 @@ mod tests {
             config.note_taking_dir
         );
     }
+
     #[test]
     fn config_default_editor_paths_exist() {
         let config = Config::default();
@@ -78,6 +319,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validate_requires_the_notes_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            note_taking_dir: dir.path().display().to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        config.note_taking_dir = dir.path().join("missing").display().to_string();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::MissingNotesDir(_))
+        ));
+    }
+
+    #[test]
+    fn filename_scheme_is_kebab_case() {
+        let config = Config::from_toml(r#"filename_scheme = "id-slug""#).unwrap();
+        assert_eq!(config.filename_scheme, FilenameScheme::IdSlug);
+        assert!(Config::from_toml(r#"filename_scheme = "uuid""#).is_err());
+    }
+
+    #[test]
+    fn select_vault_switches_notes_dir() {
+        let mut config = Config::from_toml(
+            r#"
+            note_taking_dir = "/tmp/notes"
+
+            [vaults.work]
+            note_taking_dir = "/tmp/work"
+            "#,
+        )
+        .unwrap();
+
+        config.select_vault("work").unwrap();
+        assert_eq!(config.note_taking_dir, "/tmp/work");
+        assert_eq!(config.vault.as_deref(), Some("work"));
+
+        assert!(matches!(
+            config.select_vault("personal"),
+            Err(ConfigError::UnknownVault(_))
+        ));
+    }
+
+    #[test]
+    fn notes_dir_env_wins_over_the_vault() {
+        let mut config = Config::from_toml(
+            r#"
+            default_vault = "work"
+
+            [vaults.work]
+            note_taking_dir = "/tmp/work"
+            "#,
+        )
+        .unwrap();
+
+        config
+            .resolve(None, |k| {
+                (k == "NT_NOTES_DIR").then(|| "/srv/notes".to_string())
+            })
+            .unwrap();
+        assert_eq!(config.note_taking_dir, "/srv/notes");
+        assert_eq!(config.vault, None);
+    }
+
+    #[test]
+    fn explicit_config_file_must_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = Config::load(Some(&dir.path().join("config.toml")), None).unwrap_err();
+        assert!(matches!(err, ConfigError::Io { .. }));
+    }
+
     // Check the directory exists by trying to list the files in it
 
     // let dir = std::fs::read_dir(&config.note_taking_dir);
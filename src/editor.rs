@@ -0,0 +1,171 @@
+use crate::config::Config;
+use duct::cmd;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where to put the cursor, 1-based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+/// Open `file` in the configured editor and wait for it to exit
+pub fn open(config: &Config, file: &Path) -> io::Result<()> {
+    open_at(&command(config, |k| std::env::var(k).ok()), file, None)
+}
+
+/// Open `file` with `editor`, a command line like `code --wait`, at `position`
+pub fn open_at(editor: &str, file: &Path, position: Option<Position>) -> io::Result<()> {
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No editor configured"))?;
+    let mut args: Vec<OsString> = words.map(OsString::from).collect();
+    args.extend(position_args(program, file, position));
+    cmd(program, args).run().map(|_| ())
+}
+
+/// The editor command for `default_editor`: `vim` runs `vim_path`, `vscode`
+/// runs `vscode_path` and `env` runs `$VISUAL` or `$EDITOR` (else `vim_path`),
+/// anything else is run as a command. Variables are read with `lookup`.
+pub fn command<F: Fn(&str) -> Option<String>>(config: &Config, lookup: F) -> String {
+    match config.default_editor.as_str() {
+        "vim" | "nvim" | "neovim" => config.vim_path.clone(),
+        "vscode" | "code" | "codium" => config.vscode_path.clone(),
+        "env" => lookup("VISUAL")
+            .or_else(|| lookup("EDITOR"))
+            .filter(|e| !e.trim().is_empty())
+            .unwrap_or_else(|| config.vim_path.clone()),
+        other => other.to_string(),
+    }
+}
+
+/// The arguments opening `file` at `position` in `program`, editors that
+/// aren't known only get the file
+fn position_args(program: &str, file: &Path, position: Option<Position>) -> Vec<OsString> {
+    let name = Path::new(program)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let Some(Position { line, column }) = position else {
+        return vec![file.into()];
+    };
+    let with_column = |sep: &str| match column {
+        Some(c) => format!("{line}{sep}{c}"),
+        None => line.to_string(),
+    };
+    let mut suffixed = file.as_os_str().to_owned();
+    suffixed.push(format!(":{}", with_column(":")));
+    match name.as_str() {
+        "vim" | "nvim" | "vi" | "gvim" | "mvim" => {
+            let mut args: Vec<OsString> = vec![format!("+{line}").into()];
+            if let Some(c) = column {
+                args.push(format!("+normal! {c}|").into());
+            }
+            args.push(file.into());
+            args
+        }
+        "nano" => vec![format!("+{}", with_column(",")).into(), file.into()],
+        "emacs" | "emacsclient" | "kak" | "micro" => {
+            vec![format!("+{}", with_column(":")).into(), file.into()]
+        }
+        "code" | "codium" | "code-insiders" | "cursor" => vec!["--goto".into(), suffixed],
+        "hx" | "helix" | "subl" | "zed" => vec![suffixed],
+        _ => vec![file.into()],
+    }
+}
+
+/// Split `FILE:LINE` or `FILE:LINE:COLUMN`, unless the whole argument is an
+/// existing file
+pub fn parse_position(arg: &str) -> (PathBuf, Option<Position>) {
+    let number = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0);
+    if !Path::new(arg).exists() {
+        if let Some((rest, last)) = arg.rsplit_once(':').filter(|(_, n)| number(n).is_some()) {
+            let last = number(last).unwrap_or(1);
+            return match rest.rsplit_once(':').filter(|(_, n)| number(n).is_some()) {
+                Some((file, line)) => (
+                    PathBuf::from(file),
+                    Some(Position {
+                        line: number(line).unwrap_or(1),
+                        column: Some(last),
+                    }),
+                ),
+                None => (
+                    PathBuf::from(rest),
+                    Some(Position {
+                        line: last,
+                        column: None,
+                    }),
+                ),
+            };
+        }
+    }
+    (PathBuf::from(arg), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(program: &str, line: usize, column: Option<usize>) -> Vec<String> {
+        position_args(program, Path::new("a.md"), Some(Position { line, column }))
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn each_editor_gets_its_own_position_syntax() {
+        assert_eq!(args("/usr/bin/nvim", 42, None), ["+42", "a.md"]);
+        assert_eq!(args("vim", 4, Some(2)), ["+4", "+normal! 2|", "a.md"]);
+        assert_eq!(
+            args("/usr/bin/codium", 42, Some(3)),
+            ["--goto", "a.md:42:3"]
+        );
+        assert_eq!(args("hx", 42, None), ["a.md:42"]);
+        assert_eq!(args("nano", 4, Some(2)), ["+4,2", "a.md"]);
+        assert_eq!(args("ed", 4, Some(2)), ["a.md"]);
+        assert_eq!(
+            position_args("vim", Path::new("a.md"), None),
+            [OsString::from("a.md")]
+        );
+    }
+
+    #[test]
+    fn positions_are_split_from_the_file() {
+        let at = |line, column| Some(Position { line, column });
+        assert_eq!(parse_position("a.md"), (PathBuf::from("a.md"), None));
+        assert_eq!(
+            parse_position("a.md:42"),
+            (PathBuf::from("a.md"), at(42, None))
+        );
+        assert_eq!(
+            parse_position("dir/a.md:4:2"),
+            (PathBuf::from("dir/a.md"), at(4, Some(2)))
+        );
+        assert_eq!(parse_position("a.md:x"), (PathBuf::from("a.md:x"), None));
+    }
+
+    #[test]
+    fn env_uses_visual_then_editor() {
+        let config = Config {
+            default_editor: "env".to_string(),
+            ..Config::default()
+        };
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |k: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == k)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(
+            command(&config, env(&[("EDITOR", "nano"), ("VISUAL", "code -w")])),
+            "code -w"
+        );
+        assert_eq!(command(&config, env(&[("EDITOR", "nano")])), "nano");
+        assert_eq!(command(&config, env(&[])), config.vim_path);
+    }
+}
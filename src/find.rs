@@ -0,0 +1,180 @@
+use crate::backlinks::display_path;
+use crate::config::Config;
+use crate::links;
+use crate::metadata;
+use crate::notes_map;
+use crate::output::{self, Format, Record};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// A `Record` for a note found by name
+#[derive(Debug, Serialize)]
+struct FindRecord {
+    #[serde(flatten)]
+    record: Record,
+    /// The file name, title or alias that matched best
+    matched: String,
+}
+
+/// A note and how well one of its names matches the query
+#[derive(Debug, PartialEq)]
+struct Found {
+    path: PathBuf,
+    title: Option<String>,
+    matched: String,
+    score: f32,
+}
+
+/// Print up to `count` notes whose file name, title or alias matches `query`,
+/// the best match first
+pub fn run(config: Config, query: &str, count: usize, absolute: bool, format: Format) {
+    let notes_dir = notes_map::notes_dir(&config);
+
+    let found = find(&links::note_files(&notes_dir), query);
+    let mut records = vec![];
+    for note in found.into_iter().take(count) {
+        let shown = display_path(&notes_dir, &note.path, absolute);
+        if format == Format::Plain {
+            println!("{shown}");
+        } else {
+            let mut record = Record::new(shown);
+            record.title = note.title;
+            record.score = Some(note.score);
+            records.push(FindRecord {
+                record,
+                matched: note.matched,
+            });
+        }
+    }
+    output::print_json(&records, format);
+}
+
+/// The notes matching `query` by file name, title or alias, ordered by score
+/// then path
+fn find(files: &[PathBuf], query: &str) -> Vec<Found> {
+    let mut found: Vec<Found> = files
+        .iter()
+        .filter_map(|path| {
+            let content = fs::read_to_string(path).unwrap_or_default();
+            let meta = metadata::parse(&content);
+            let stem = path.file_stem()?.to_string_lossy().into_owned();
+            let (matched, score) = std::iter::once(stem)
+                .chain(meta.title.clone())
+                .chain(meta.aliases)
+                .filter_map(|name| Some((score(query, &name)?, name)))
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(score, name)| (name, score))?;
+            Some(Found {
+                path: path.clone(),
+                title: meta.title,
+                matched,
+                score,
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.path.cmp(&b.path)));
+    found
+}
+
+/// How well `name` matches `query`, ignoring case and treating `-`, `_` and
+/// spaces alike. Exact matches score 100, then prefixes, then matches at the
+/// start of a word, then substrings, then the query's characters in order.
+/// Within a kind, a match covering more of the name scores higher.
+pub fn score(query: &str, name: &str) -> Option<f32> {
+    let normalize = |s: &str| -> Vec<char> {
+        s.trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c == '-' || c == '_' { ' ' } else { c })
+            .collect()
+    };
+    let query = normalize(query);
+    let name = normalize(name);
+    if query.is_empty() || name.is_empty() {
+        return None;
+    }
+    let coverage = query.len() as f32 / name.len() as f32;
+    if query == name {
+        return Some(100.0);
+    }
+    let word_start = |i: usize| i == 0 || !name[i - 1].is_alphanumeric();
+    let substring = (0..name.len())
+        .filter(|&i| name[i..].starts_with(&query))
+        .map(|i| match (i, word_start(i)) {
+            (0, _) => 80u8,
+            (_, true) => 60,
+            (_, false) => 40,
+        })
+        .max();
+    if let Some(kind) = substring {
+        return Some(f32::from(kind) + 10.0 * coverage);
+    }
+    // The shortest window holding the query's characters in order
+    let mut best: Option<usize> = None;
+    for start in (0..name.len()).filter(|&i| name[i] == query[0]) {
+        let mut q = 0;
+        for (i, c) in name[start..].iter().enumerate() {
+            if *c == query[q] {
+                q += 1;
+                if q == query.len() {
+                    best = Some(best.map_or(i + 1, |b| b.min(i + 1)));
+                    break;
+                }
+            }
+        }
+    }
+    best.map(|window| 30.0 * query.len() as f32 / window as f32 + 5.0 * coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_and_prefix_matches_rank_first() {
+        let by = |q| -> Vec<Option<u32>> {
+            ["rust", "rusty nail", "learn rust", "trust", "r-u-s-t", "go"]
+                .iter()
+                .map(|n| score(q, n).map(|s| s as u32))
+                .collect()
+        };
+        let scores = by("Rust");
+        assert_eq!(scores[5], None);
+        let ranked: Vec<u32> = scores.into_iter().flatten().collect();
+        assert!(ranked.windows(2).all(|w| w[0] > w[1]), "{ranked:?}");
+        assert_eq!(score("borrow-checker", "Borrow Checker"), Some(100.0));
+    }
+
+    #[test]
+    fn titles_and_aliases_are_matched() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("20240101.md"),
+            "---\ntitle: Rust Lifetimes\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("borrowing.md"),
+            "---\naliases: [rust ownership]\n---\n",
+        )
+        .unwrap();
+        fs::write(root.join("python.md"), "mentions rust in the content").unwrap();
+        let found = find(&links::note_files(root), "rust");
+        let matched: Vec<(&str, &str)> = found
+            .iter()
+            .map(|f| {
+                let name = f.path.file_name().unwrap().to_str().unwrap();
+                (name, f.matched.as_str())
+            })
+            .collect();
+        assert_eq!(
+            matched,
+            vec![
+                ("20240101.md", "Rust Lifetimes"),
+                ("borrowing.md", "rust ownership"),
+            ]
+        );
+    }
+}
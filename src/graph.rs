@@ -0,0 +1,595 @@
+use crate::backlinks::relpath;
+use crate::config::Config;
+use crate::links::LinkKind;
+use crate::metadata;
+use crate::notes_map::{self, NotesMap};
+use crate::output::{self, Format};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which notes to include
+#[derive(Debug, Default)]
+pub struct Scope {
+    /// Only notes under this directory, relative to the notes directory
+    pub dir: Option<PathBuf>,
+    /// Only notes within `hops` links of this one, in either direction
+    pub around: Option<PathBuf>,
+    pub hops: usize,
+}
+
+/// The links between notes, as indices into `paths`
+#[derive(Debug)]
+pub struct Graph {
+    /// Every note, ordered by path
+    pub paths: Vec<PathBuf>,
+    /// Source, target and kind, without duplicates
+    pub edges: Vec<(usize, usize, LinkKind)>,
+}
+
+#[derive(Debug, Serialize)]
+struct Node {
+    path: String,
+    title: Option<String>,
+    tags: Vec<String>,
+    words: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Edge {
+    source: String,
+    target: String,
+    kind: LinkKind,
+}
+
+#[derive(Serialize)]
+struct Export<'a> {
+    nodes: &'a [Node],
+    edges: &'a [Edge],
+}
+
+/// A note's place in the graph, for `graph stats`
+#[derive(Debug, Serialize)]
+struct NoteStats {
+    path: String,
+    title: Option<String>,
+    /// Number of distinct notes linking here
+    #[serde(rename = "in")]
+    incoming: usize,
+    /// Number of distinct notes linked to
+    #[serde(rename = "out")]
+    outgoing: usize,
+    pagerank: f64,
+    /// Connected component, 0 is the largest
+    component: usize,
+    /// Community of closely linked notes, 0 is the largest
+    cluster: usize,
+}
+
+/// A line of `--format jsonl`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Element<'a> {
+    Node(&'a Node),
+    Edge(&'a Edge),
+}
+
+/// Load the vault's graph restricted to `scope`
+pub fn load(config: &Config, scope: &Scope, nested: bool, verbose: bool) -> (PathBuf, Graph) {
+    let (notes_dir, map) = notes_map::load(config, nested, verbose);
+
+    let mut graph = Graph::new(&map);
+    if let Some(dir) = &scope.dir {
+        let dir = notes_dir
+            .join(dir)
+            .canonicalize()
+            .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", dir));
+        graph = graph.within(&dir);
+    }
+    if let Some(note) = &scope.around {
+        let note = note
+            .canonicalize()
+            .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", note));
+        graph = graph.around(&note, scope.hops);
+    }
+    (notes_dir, graph)
+}
+
+pub fn export(config: Config, scope: &Scope, nested: bool, verbose: bool, format: Format) {
+    let (notes_dir, graph) = load(&config, scope, nested, verbose);
+    let shown = |i: usize| relpath(&notes_dir, &graph.paths[i]);
+
+    let nodes: Vec<Node> = graph
+        .paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let content = fs::read_to_string(path).unwrap_or_default();
+            let meta = metadata::parse(&content);
+            let (_, body) = metadata::split_frontmatter(&content);
+            Node {
+                path: shown(i),
+                title: meta.title,
+                tags: meta.tags,
+                words: body.split_whitespace().count(),
+            }
+        })
+        .collect();
+    let edges: Vec<Edge> = graph
+        .edges
+        .iter()
+        .map(|&(source, target, kind)| Edge {
+            source: shown(source),
+            target: shown(target),
+            kind,
+        })
+        .collect();
+
+    match format {
+        Format::Plain | Format::Dot => print!("{}", dot(&nodes, &edges)),
+        Format::Graphml => print!("{}", graphml(&nodes, &edges)),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&Export {
+                nodes: &nodes,
+                edges: &edges
+            })
+            .expect("Unable to serialize graph")
+        ),
+        Format::Jsonl => {
+            let nodes = nodes.iter().map(Element::Node);
+            for element in nodes.chain(edges.iter().map(Element::Edge)) {
+                println!(
+                    "{}",
+                    serde_json::to_string(&element).expect("Unable to serialize graph")
+                );
+            }
+        }
+    }
+}
+
+/// Print the hubs, islands and clusters, or every note's stats as JSON
+pub fn stats(
+    config: Config,
+    scope: &Scope,
+    top: usize,
+    nested: bool,
+    verbose: bool,
+    format: Format,
+) {
+    let (notes_dir, graph) = load(&config, scope, nested, verbose);
+    let (incoming, outgoing) = graph.degrees();
+    let rank = graph.pagerank();
+    let components = graph.components();
+    let clusters = graph.clusters();
+
+    let mut notes: Vec<NoteStats> = (0..graph.paths.len())
+        .map(|i| NoteStats {
+            path: relpath(&notes_dir, &graph.paths[i]),
+            title: None,
+            incoming: incoming[i],
+            outgoing: outgoing[i],
+            pagerank: rank[i],
+            component: components[i],
+            cluster: clusters[i],
+        })
+        .collect();
+    notes.sort_by(|a, b| b.pagerank.total_cmp(&a.pagerank));
+
+    if format != Format::Plain {
+        for note in notes.iter_mut() {
+            let path = notes_dir.join(&note.path);
+            note.title = fs::read_to_string(path)
+                .ok()
+                .and_then(|c| metadata::parse(&c).title);
+        }
+        output::print_json(&notes, format);
+        return;
+    }
+
+    let count = |labels: &[usize]| labels.iter().max().map_or(0, |m| m + 1);
+    let links: usize = outgoing.iter().sum();
+    println!(
+        "{} notes, {links} links, {} components, {} clusters",
+        notes.len(),
+        count(&components),
+        count(&clusters)
+    );
+
+    println!("\nHubs\n{:>8} {:>4} {:>4}  note", "pagerank", "in", "out");
+    for note in notes.iter().take(top) {
+        println!(
+            "{:>8.4} {:>4} {:>4}  {}",
+            note.pagerank, note.incoming, note.outgoing, note.path
+        );
+    }
+
+    // Everything outside the largest component is cut off from the rest
+    println!("\nIslands");
+    for component in 1..count(&components) {
+        let paths: Vec<String> = (0..graph.paths.len())
+            .filter(|&i| components[i] == component)
+            .map(|i| relpath(&notes_dir, &graph.paths[i]))
+            .collect();
+        println!("{}", paths.join(", "));
+    }
+
+    println!("\nClusters");
+    for cluster in 0..count(&clusters).min(top) {
+        let size = clusters.iter().filter(|&&c| c == cluster).count();
+        // Notes are ordered by pagerank, so the first is the most central
+        let centre = notes.iter().find(|n| n.cluster == cluster);
+        println!("{size:>4} notes around {}", centre.map_or("", |n| &n.path));
+    }
+}
+
+impl Graph {
+    pub fn new(map: &NotesMap) -> Graph {
+        let paths: Vec<PathBuf> = map.notes().map(|(p, _)| p.to_path_buf()).collect();
+        let mut edges = BTreeSet::new();
+        for (source, (_, note)) in map.notes().enumerate() {
+            for resolved in &note.links {
+                let Some(target) = &resolved.target else {
+                    continue;
+                };
+                if let Ok(target) = paths.binary_search(target) {
+                    edges.insert((source, target, resolved.link.kind));
+                }
+            }
+        }
+        Graph {
+            paths,
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// Only the notes under `dir`
+    pub fn within(self, dir: &Path) -> Graph {
+        let keep = self.paths.iter().map(|p| p.starts_with(dir)).collect();
+        self.retain(keep)
+    }
+
+    /// Only the notes at most `hops` links away from `note`, ignoring direction
+    pub fn around(self, note: &Path, hops: usize) -> Graph {
+        let mut distance = vec![None; self.paths.len()];
+        let mut queue = VecDeque::new();
+        if let Ok(start) = self.paths.binary_search_by(|p| p.as_path().cmp(note)) {
+            distance[start] = Some(0);
+            queue.push_back(start);
+        }
+        let neighbours = self.neighbours();
+        while let Some(node) = queue.pop_front() {
+            let next = distance[node].unwrap_or_default() + 1;
+            if next > hops {
+                continue;
+            }
+            for &neighbour in &neighbours[node] {
+                if distance[neighbour].is_none() {
+                    distance[neighbour] = Some(next);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        let keep = distance.iter().map(Option::is_some).collect();
+        self.retain(keep)
+    }
+
+    /// The notes linked to or from each note, without duplicates
+    fn neighbours(&self) -> Vec<Vec<usize>> {
+        let mut neighbours = vec![vec![]; self.paths.len()];
+        for (source, target) in self.links() {
+            neighbours[source].push(target);
+            neighbours[target].push(source);
+        }
+        for list in neighbours.iter_mut() {
+            list.sort();
+            list.dedup();
+        }
+        neighbours
+    }
+
+    /// Distinct links between different notes, ignoring their kind
+    fn links(&self) -> Vec<(usize, usize)> {
+        let mut links: Vec<(usize, usize)> = self
+            .edges
+            .iter()
+            .filter(|(s, t, _)| s != t)
+            .map(|&(s, t, _)| (s, t))
+            .collect();
+        // Edges are sorted, so duplicates are adjacent
+        links.dedup();
+        links
+    }
+
+    /// The number of notes linking to and linked from each note
+    pub fn degrees(&self) -> (Vec<usize>, Vec<usize>) {
+        let mut incoming = vec![0; self.paths.len()];
+        let mut outgoing = vec![0; self.paths.len()];
+        for (source, target) in self.links() {
+            outgoing[source] += 1;
+            incoming[target] += 1;
+        }
+        (incoming, outgoing)
+    }
+
+    /// PageRank of each note, notes without links spread their rank evenly
+    pub fn pagerank(&self) -> Vec<f64> {
+        const DAMPING: f64 = 0.85;
+        const MAX_ITERATIONS: usize = 100;
+        const TOLERANCE: f64 = 1e-10;
+
+        let n = self.paths.len();
+        let links = self.links();
+        let (_, outgoing) = self.degrees();
+        let mut rank = vec![1.0 / n as f64; n];
+        for _ in 0..MAX_ITERATIONS {
+            let dangling: f64 = (0..n).filter(|&i| outgoing[i] == 0).map(|i| rank[i]).sum();
+            let base = (1.0 - DAMPING + DAMPING * dangling) / n as f64;
+            let mut next = vec![base; n];
+            for &(source, target) in &links {
+                next[target] += DAMPING * rank[source] / outgoing[source] as f64;
+            }
+            let change: f64 = rank.iter().zip(&next).map(|(a, b)| (a - b).abs()).sum();
+            rank = next;
+            if change < TOLERANCE {
+                break;
+            }
+        }
+        rank
+    }
+
+    /// The connected component of each note, ignoring link direction
+    pub fn components(&self) -> Vec<usize> {
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let mut parent: Vec<usize> = (0..self.paths.len()).collect();
+        for (source, target) in self.links() {
+            let (a, b) = (root(&mut parent, source), root(&mut parent, target));
+            parent[a.max(b)] = a.min(b);
+        }
+        let labels = (0..parent.len()).map(|i| root(&mut parent, i)).collect();
+        by_size(labels)
+    }
+
+    /// Communities of notes, by greedily moving each note to the neighbouring
+    /// community that most increases modularity (the first phase of Louvain)
+    pub fn clusters(&self) -> Vec<usize> {
+        const MAX_ROUNDS: usize = 100;
+
+        let n = self.paths.len();
+        let neighbours = self.neighbours();
+        let degree: Vec<f64> = neighbours.iter().map(|l| l.len() as f64).collect();
+        let total: f64 = degree.iter().sum();
+        if total == 0.0 {
+            return (0..n).collect();
+        }
+
+        let mut community: Vec<usize> = (0..n).collect();
+        // Sum of the degrees in each community
+        let mut weight = degree.clone();
+        for _ in 0..MAX_ROUNDS {
+            let mut moved = false;
+            for i in 0..n {
+                let current = community[i];
+                weight[current] -= degree[i];
+                let mut links_to: BTreeMap<usize, f64> = BTreeMap::new();
+                for &j in &neighbours[i] {
+                    *links_to.entry(community[j]).or_default() += 1.0;
+                }
+                let gain = |c: usize, k: f64| k - weight[c] * degree[i] / total;
+                let mut best = (
+                    current,
+                    gain(current, *links_to.get(&current).unwrap_or(&0.0)),
+                );
+                for (&c, &k) in &links_to {
+                    if gain(c, k) > best.1 + f64::EPSILON {
+                        best = (c, gain(c, k));
+                    }
+                }
+                community[i] = best.0;
+                weight[best.0] += degree[i];
+                moved |= best.0 != current;
+            }
+            if !moved {
+                break;
+            }
+        }
+        by_size(community)
+    }
+
+    fn retain(self, keep: Vec<bool>) -> Graph {
+        let mut index = vec![None; self.paths.len()];
+        let mut paths = vec![];
+        for (i, path) in self.paths.into_iter().enumerate() {
+            if keep[i] {
+                index[i] = Some(paths.len());
+                paths.push(path);
+            }
+        }
+        let edges = self
+            .edges
+            .into_iter()
+            .filter_map(|(s, t, kind)| Some((index[s]?, index[t]?, kind)))
+            .collect();
+        Graph { paths, edges }
+    }
+}
+
+/// Renumber labels from the largest group to the smallest,
+/// groups of the same size in order of their first note
+fn by_size(labels: Vec<usize>) -> Vec<usize> {
+    let mut sizes: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    for (i, &label) in labels.iter().enumerate() {
+        sizes.entry(label).or_insert((0, i)).0 += 1;
+    }
+    let mut order: Vec<(usize, (usize, usize))> = sizes.into_iter().collect();
+    order.sort_by_key(|&(_, (size, first))| (std::cmp::Reverse(size), first));
+    let renumbered: BTreeMap<usize, usize> = order
+        .into_iter()
+        .enumerate()
+        .map(|(new, (old, _))| (old, new))
+        .collect();
+    labels.iter().map(|l| renumbered[l]).collect()
+}
+
+fn dot(nodes: &[Node], edges: &[Edge]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut out = String::from("digraph notes {\n");
+    for node in nodes {
+        out.push_str(&format!(
+            "  {} [label={}, tags={}, words={}];\n",
+            quote(&node.path),
+            quote(node.title.as_deref().unwrap_or(&node.path)),
+            quote(&node.tags.join(",")),
+            node.words
+        ));
+    }
+    for edge in edges {
+        out.push_str(&format!(
+            "  {} -> {} [kind={}];\n",
+            quote(&edge.source),
+            quote(&edge.target),
+            edge.kind.as_str()
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn graphml(nodes: &[Node], edges: &[Edge]) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n",
+        "  <key id=\"tags\" for=\"node\" attr.name=\"tags\" attr.type=\"string\"/>\n",
+        "  <key id=\"words\" for=\"node\" attr.name=\"words\" attr.type=\"int\"/>\n",
+        "  <key id=\"kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+        "  <graph id=\"notes\" edgedefault=\"directed\">\n",
+    ));
+    for node in nodes {
+        out.push_str(&format!("    <node id=\"{}\">\n", xml_escape(&node.path)));
+        if let Some(title) = &node.title {
+            out.push_str(&format!(
+                "      <data key=\"title\">{}</data>\n",
+                xml_escape(title)
+            ));
+        }
+        out.push_str(&format!(
+            "      <data key=\"tags\">{}</data>\n      <data key=\"words\">{}</data>\n    </node>\n",
+            xml_escape(&node.tags.join(",")),
+            node.words
+        ));
+    }
+    for edge in edges {
+        out.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\">\n      <data key=\"kind\">{}</data>\n    </edge>\n",
+            xml_escape(&edge.source),
+            xml_escape(&edge.target),
+            edge.kind.as_str()
+        ));
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(graph: &Graph) -> Vec<String> {
+        graph
+            .paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn graph_filters_keep_edges_between_kept_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.md"), "[b](b.md) [[b]] [b](b.md)").unwrap();
+        std::fs::write(root.join("b.md"), "[c](sub/c.md)").unwrap();
+        std::fs::write(root.join("sub/c.md"), "[[d]]").unwrap();
+        std::fs::write(root.join("sub/d.md"), "[x](x.md)").unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+        let graph = Graph::new(&map);
+        assert_eq!(
+            graph.edges,
+            vec![
+                (0, 1, LinkKind::Inline),
+                (0, 1, LinkKind::Wiki),
+                (1, 2, LinkKind::Inline),
+                (2, 3, LinkKind::Wiki),
+            ]
+        );
+
+        let sub = Graph::new(&map).within(&root.join("sub"));
+        assert_eq!(names(&sub), vec!["c.md", "d.md"]);
+        assert_eq!(sub.edges, vec![(0, 1, LinkKind::Wiki)]);
+
+        let around = Graph::new(&map).around(&root.join("b.md"), 1);
+        assert_eq!(names(&around), vec!["a.md", "b.md", "c.md"]);
+        let around = Graph::new(&map).around(&root.join("b.md"), 0);
+        assert_eq!(names(&around), vec!["b.md"]);
+    }
+
+    #[test]
+    fn stats_find_hubs_components_and_clusters() {
+        // Two triangles joined at c-d, all pointing at their first note, and an isolated note
+        let links = [(1, 0), (2, 0), (1, 2), (2, 3), (4, 3), (5, 3), (4, 5)];
+        let graph = Graph {
+            paths: (0..7).map(|i| PathBuf::from(format!("{i}.md"))).collect(),
+            edges: links.iter().map(|&(s, t)| (s, t, LinkKind::Wiki)).collect(),
+        };
+
+        let (incoming, outgoing) = graph.degrees();
+        assert_eq!(incoming, vec![2, 0, 1, 3, 0, 1, 0]);
+        assert_eq!(outgoing, vec![0, 2, 2, 0, 2, 1, 0]);
+
+        let rank = graph.pagerank();
+        assert!((rank.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        let hub = (0..7).max_by(|&a, &b| rank[a].total_cmp(&rank[b]));
+        assert_eq!(hub, Some(3));
+
+        assert_eq!(graph.components(), vec![0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(graph.clusters(), vec![0, 0, 0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn dot_and_graphml_are_escaped() {
+        let nodes = [Node {
+            path: "a \"b\".md".to_string(),
+            title: Some("A & <B>".to_string()),
+            tags: vec!["x".to_string(), "y".to_string()],
+            words: 3,
+        }];
+        let edges = [Edge {
+            source: "a \"b\".md".to_string(),
+            target: "a \"b\".md".to_string(),
+            kind: LinkKind::Wiki,
+        }];
+        let dot = dot(&nodes, &edges);
+        assert!(dot.contains(r#"  "a \"b\".md" [label="A & <B>", tags="x,y", words=3];"#));
+        assert!(dot.contains(r#"  "a \"b\".md" -> "a \"b\".md" [kind=wiki];"#));
+        let graphml = graphml(&nodes, &edges);
+        assert!(graphml.contains(r#"<node id="a &quot;b&quot;.md">"#));
+        assert!(graphml.contains("<data key=\"title\">A &amp; &lt;B&gt;</data>"));
+    }
+}
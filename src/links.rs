@@ -0,0 +1,567 @@
+use crate::metadata;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use walkdir::WalkDir;
+
+/// Files that can link and be linked to
+pub const NOTE_EXTENSIONS: [&str; 2] = ["md", "org"];
+
+/// Tried in order when a link has no extension
+const IMPLICIT_EXTENSIONS: [&str; 2] = ["md", "org"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// `[text](target)`
+    Inline,
+    /// `[label]: target`
+    Reference,
+    /// `[[target#heading|alias]]`
+    Wiki,
+    /// `[[file:target::heading][description]]`
+    Org,
+}
+
+impl LinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkKind::Inline => "inline",
+            LinkKind::Reference => "reference",
+            LinkKind::Wiki => "wiki",
+            LinkKind::Org => "org",
+        }
+    }
+}
+
+/// A link found in a note
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    pub kind: LinkKind,
+    /// The path or URL, percent-decoded, empty for links within the same note
+    pub target: String,
+    /// Heading, block id or org search after the target
+    pub anchor: Option<String>,
+    /// 1-based line number
+    pub line: usize,
+    /// Byte range of the target as written in its line, without the anchor
+    pub span: Range<usize>,
+    /// The text of a markdown link, alias of a wikilink or description of an org link
+    pub text: Option<String>,
+}
+
+impl Link {
+    /// `https:`, `mailto:` etc., but not a Windows drive letter
+    pub fn is_external(&self) -> bool {
+        match self.target.split_once(':') {
+            Some((scheme, _)) => {
+                scheme.len() > 1
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+            }
+            None => false,
+        }
+    }
+}
+
+// `[[...]]` and org's `[[...][...]]`, optionally embedded with `!`
+static DOUBLE_BRACKET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"!?\[\[([^\[\]]+)\](?:\[([^\[\]]*)\])?\]").expect("Failed to create regex")
+});
+
+// `[text](target "title")`, images start with `!` and are skipped
+static INLINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"!?\[([^\[\]]*)\]\(\s*(<[^>]*>|[^)\s]+)(?:\s+"[^"]*")?\s*\)"#)
+        .expect("Failed to create regex")
+});
+
+// `[label]: target` at the start of a line, `[^1]: ...` is a footnote
+static REFERENCE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^ {0,3}\[[^\]^][^\]]*\]:\s*(<[^>]*>|\S+)").expect("Failed to create regex")
+});
+
+// A bare URL, which isn't parsed as a link but isn't prose either
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[a-zA-Z][a-zA-Z0-9+.-]*://\S+").expect("Failed to create regex"));
+
+// `^block-id` at the end of a paragraph or list item
+static BLOCK_ID: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|\s)\^([\w-]+)\s*$").expect("Failed to create regex"));
+
+// Org headings without their keyword and tags, custom ids and `<<targets>>`
+static ORG_ANCHOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\*+\s+(?:(?:TODO|DONE)\s+)?(.*?)(?:\s+:[\w@#%:]+:)?\s*$|^\s*:CUSTOM_ID:\s*(\S+)|<<([^<>]+)>>")
+        .expect("Failed to create regex")
+});
+
+static CODE_SPAN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"`[^`]*`").expect("Failed to create regex"));
+
+/// Extract every link, `org` selects the syntax of org-mode files,
+/// where every `[[...]]` is an org link
+pub fn extract(content: &str, org: bool) -> Vec<Link> {
+    let mut links = vec![];
+    let mut in_fence = false;
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start().to_lowercase();
+        let fence = match org {
+            true => ["#+begin_src", "#+end_src"]
+                .iter()
+                .any(|f| trimmed.starts_with(f)),
+            false => trimmed.starts_with("```") || trimmed.starts_with("~~~"),
+        };
+        if fence {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        // Blank out code spans so that offsets stay the same
+        let line = CODE_SPAN.replace_all(line, |c: &regex::Captures| " ".repeat(c[0].len()));
+        let number = i + 1;
+        let mut line_links = vec![];
+
+        for caps in DOUBLE_BRACKET.captures_iter(&line) {
+            let inner = caps.get(1).expect("group 1 always matches");
+            let is_org = org || caps.get(2).is_some() || inner.as_str().starts_with("file:");
+            line_links.push(if is_org {
+                org_link(inner, caps.get(2).map(|d| d.as_str()), number)
+            } else {
+                wiki_link(inner, number)
+            });
+        }
+        if !org {
+            for caps in INLINE.captures_iter(&line) {
+                if caps[0].starts_with('!') {
+                    continue;
+                }
+                line_links.push(markdown_link(
+                    LinkKind::Inline,
+                    caps.get(2),
+                    Some(&caps[1]),
+                    number,
+                ));
+            }
+            if let Some(caps) = REFERENCE.captures(&line) {
+                line_links.push(markdown_link(
+                    LinkKind::Reference,
+                    caps.get(1),
+                    None,
+                    number,
+                ));
+            }
+        }
+        // Each syntax is matched separately, put them back in source order
+        line_links.sort_by_key(|l| l.span.start);
+        links.extend(line_links);
+    }
+    links
+}
+
+/// The headings and block ids (with their `^`) a link can point at
+pub fn anchors(content: &str, org: bool) -> Vec<String> {
+    let mut anchors = match org {
+        true => vec![],
+        false => metadata::parse(content).headings,
+    };
+    for line in content.lines() {
+        if org {
+            if let Some(caps) = ORG_ANCHOR.captures(line) {
+                let anchor = (1..=3).find_map(|i| caps.get(i));
+                anchors.extend(anchor.map(|a| a.as_str().to_string()));
+            }
+        } else if let Some(caps) = BLOCK_ID.captures(line) {
+            anchors.push(format!("^{}", &caps[1]));
+        }
+    }
+    anchors
+}
+
+/// Whether `anchor` names one of `anchors`. Block ids match exactly, headings
+/// by their slug so that `#set-up` and `[[note#Set up]]` both find `# Set Up`.
+pub fn anchor_matches(anchor: &str, anchors: &[String]) -> bool {
+    if anchor.starts_with('^') {
+        return anchors.iter().any(|a| a == anchor);
+    }
+    let anchor = slug(anchor);
+    anchors
+        .iter()
+        .any(|a| !a.starts_with('^') && slug(a) == anchor)
+}
+
+/// GitHub style, lowercase with punctuation dropped and spaces as `-`
+fn slug(heading: &str) -> String {
+    let slug: String = heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect();
+    // Tolerate the repeated dashes that differ between slug styles
+    slug.split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Byte ranges of the links and code spans in a line, where text isn't prose
+pub fn non_prose(line: &str) -> Vec<Range<usize>> {
+    [&*DOUBLE_BRACKET, &*INLINE, &*REFERENCE, &*URL, &*CODE_SPAN]
+        .iter()
+        .flat_map(|re| re.find_iter(line).map(|m| m.range()))
+        .collect()
+}
+
+fn markdown_link(
+    kind: LinkKind,
+    target: Option<regex::Match>,
+    text: Option<&str>,
+    line: usize,
+) -> Link {
+    let target = target.expect("group 1 always matches");
+    let (mut start, mut raw) = (target.start(), target.as_str());
+    if let Some(inner) = raw.strip_prefix('<') {
+        (start, raw) = (start + 1, inner.strip_suffix('>').unwrap_or(inner));
+    }
+    let (target, anchor) = split_anchor(raw, "#");
+    Link {
+        kind,
+        target: decode(target),
+        anchor: anchor.map(decode),
+        line,
+        span: start..start + target.len(),
+        text: non_empty(text),
+    }
+}
+
+/// `target#heading|alias`
+fn wiki_link(inner: regex::Match, line: usize) -> Link {
+    let (target, alias) = match inner.as_str().split_once('|') {
+        Some((target, alias)) => (target, Some(alias)),
+        None => (inner.as_str(), None),
+    };
+    let (target, anchor) = split_anchor(target, "#");
+    let start = inner.start() + target.len() - target.trim_start().len();
+    Link {
+        kind: LinkKind::Wiki,
+        target: target.trim().to_string(),
+        anchor: anchor.map(|a| a.trim().to_string()),
+        line,
+        span: start..start + target.trim().len(),
+        text: non_empty(alias),
+    }
+}
+
+/// `file:target::search`, `*heading` or `https://...`
+fn org_link(inner: regex::Match, description: Option<&str>, line: usize) -> Link {
+    let text = inner.as_str();
+    let (start, target, anchor) = match text.strip_prefix("file:") {
+        Some(file) => {
+            let (target, anchor) = split_anchor(file, "::");
+            (inner.start() + "file:".len(), target, anchor)
+        }
+        None if text.starts_with(['*', '#']) => (inner.start(), "", Some(text)),
+        None => (inner.start(), text, None),
+    };
+    Link {
+        kind: LinkKind::Org,
+        target: decode(target),
+        anchor: anchor.map(|a| a.trim_start_matches(['*', '#']).trim().to_string()),
+        line,
+        span: start..start + target.len(),
+        text: non_empty(description),
+    }
+}
+
+fn non_empty(text: Option<&str>) -> Option<String> {
+    text.map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+}
+
+fn split_anchor<'a>(target: &'a str, sep: &str) -> (&'a str, Option<&'a str>) {
+    match target.split_once(sep) {
+        Some((t, a)) => (t, Some(a)),
+        None => (target, None),
+    }
+}
+
+fn decode(s: &str) -> String {
+    urlencoding::decode(s)
+        .map(|d| d.into_owned())
+        .unwrap_or_else(|_| s.to_string())
+}
+
+/// All notes under `dir`, skipping hidden directories
+pub fn note_files(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_note(e.path()))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+pub fn is_note(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| NOTE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Resolves links to the files they point at
+pub struct Resolver {
+    notes_dir: PathBuf,
+    /// Links are relative to the linking note's directory rather than the notes directory
+    nested: bool,
+    /// Lowercase file stem and file name to paths, for wikilinks
+    by_name: HashMap<String, Vec<PathBuf>>,
+}
+
+impl Resolver {
+    pub fn new(notes_dir: &Path, files: &[PathBuf], nested: bool) -> Resolver {
+        let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for file in files {
+            let names = [file.file_stem(), file.file_name()];
+            for name in names.into_iter().flatten() {
+                let name = name.to_string_lossy().to_lowercase();
+                by_name.entry(name).or_default().push(file.clone());
+            }
+        }
+        Resolver {
+            notes_dir: notes_dir.to_path_buf(),
+            nested,
+            by_name,
+        }
+    }
+
+    /// The existing note a link points at, `None` for external, same-note
+    /// and broken links and for links to other files
+    pub fn resolve(&self, source: &Path, link: &Link) -> Option<PathBuf> {
+        self.file(source, link).filter(|path| is_note(path))
+    }
+
+    /// The existing file a link points at, a note or any other file like an
+    /// image or a PDF
+    pub fn file(&self, source: &Path, link: &Link) -> Option<PathBuf> {
+        if link.target.is_empty() || link.is_external() {
+            return None;
+        }
+        if link.kind == LinkKind::Wiki {
+            return self.resolve_wiki(source, &link.target);
+        }
+        let base = match self.nested {
+            true => source.parent().unwrap_or(&self.notes_dir),
+            false => &self.notes_dir,
+        };
+        let path = Path::new(&link.target);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            base.join(path)
+        };
+        with_extension(&normalize(&path))
+    }
+
+    /// By path from the notes directory, else by name, preferring the
+    /// source's directory and then the shortest path
+    fn resolve_wiki(&self, source: &Path, target: &str) -> Option<PathBuf> {
+        if target.contains('/') {
+            return with_extension(&normalize(&self.notes_dir.join(target)));
+        }
+        let candidates = self.by_name.get(&target.to_lowercase())?;
+        candidates
+            .iter()
+            .min_by_key(|c| (c.parent() != source.parent(), c.components().count()))
+            .cloned()
+    }
+}
+
+/// The path itself if it exists, else with each implicit extension
+fn with_extension(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    if is_note(path) {
+        return None;
+    }
+    IMPLICIT_EXTENSIONS.iter().find_map(|ext| {
+        let mut candidate = path.as_os_str().to_owned();
+        candidate.push(format!(".{ext}"));
+        let candidate = PathBuf::from(candidate);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
+/// Remove `.` and `..` without touching the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(content: &str) -> Vec<(LinkKind, String, Option<String>)> {
+        extract(content, false)
+            .into_iter()
+            .map(|l| (l.kind, l.target, l.anchor))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_links() {
+        let note = "First [d](sub/deep.md) then [[rust]].\n\
+                    see [a](a.md) and [b](<dir/b c.md#Set Up> \"title\"), ![img](x.png)\n\
+                    [ref]: notes/my%20note.md\n\
+                    [^1]: Knuth, The Art of Computer Programming\n";
+        assert_eq!(
+            targets(note),
+            vec![
+                (LinkKind::Inline, "sub/deep.md".into(), None),
+                (LinkKind::Wiki, "rust".into(), None),
+                (LinkKind::Inline, "a.md".into(), None),
+                (LinkKind::Inline, "dir/b c.md".into(), Some("Set Up".into())),
+                (LinkKind::Reference, "notes/my note.md".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn wiki_and_org_links() {
+        let note = "[[Note]] [[dir/note#Setup|the setup]] ![[embed]]\n\
+                    [[file:todo.org::*Today][today]] [[https://example.com][site]]";
+        assert_eq!(
+            targets(note),
+            vec![
+                (LinkKind::Wiki, "Note".into(), None),
+                (LinkKind::Wiki, "dir/note".into(), Some("Setup".into())),
+                (LinkKind::Wiki, "embed".into(), None),
+                (LinkKind::Org, "todo.org".into(), Some("Today".into())),
+                (LinkKind::Org, "https://example.com".into(), None),
+            ]
+        );
+        let org = extract(
+            "[[*Heading]] [[file:a.org]]\n#+BEGIN_SRC sh\n[[b]]\n#+END_SRC\n",
+            true,
+        );
+        assert_eq!(org.len(), 2);
+        assert_eq!(org[0].anchor.as_deref(), Some("Heading"));
+        assert_eq!(org[1].target, "a.org");
+    }
+
+    #[test]
+    fn link_text_alias_and_description() {
+        let note = "[the a](a.md) [[b|Bee]] [[c]] [[file:d.org][Dee]] []( e.md )";
+        let texts: Vec<Option<String>> = extract(note, false).into_iter().map(|l| l.text).collect();
+        assert_eq!(
+            texts,
+            vec![
+                Some("the a".into()),
+                Some("Bee".into()),
+                None,
+                Some("Dee".into()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_cover_the_target_as_written() {
+        let note = "[a](<b c.md#x>) [[ Note#h|alias]] [[file:my%20todo.org::*T][t]]\n[r]: r.md";
+        let lines: Vec<&str> = note.lines().collect();
+        let spans: Vec<&str> = extract(note, false)
+            .into_iter()
+            .map(|l| &lines[l.line - 1][l.span])
+            .collect();
+        assert_eq!(spans, vec!["b c.md", "Note", "my%20todo.org", "r.md"]);
+    }
+
+    #[test]
+    fn anchors_match_headings_by_slug_and_blocks_exactly() {
+        let note = "# Set Up\n## What's new?\nA paragraph ^block-1\n```\n# not a heading\n```\n";
+        let found = anchors(note, false);
+        assert_eq!(found, vec!["Set Up", "What's new?", "^block-1"]);
+        assert!(anchor_matches("set-up", &found));
+        assert!(anchor_matches("Set up", &found));
+        assert!(anchor_matches("whats-new", &found));
+        assert!(anchor_matches("^block-1", &found));
+        assert!(!anchor_matches("block-1", &found));
+        assert!(!anchor_matches("not a heading", &found));
+
+        let org = anchors(
+            "* TODO Plan :work:\n:PROPERTIES:\n:CUSTOM_ID: plan\n:END:\n<<here>>\n",
+            true,
+        );
+        assert_eq!(org, vec!["Plan", "plan", "here"]);
+    }
+
+    #[test]
+    fn code_is_not_linked_and_lines_are_counted() {
+        let note = "`[a](a.md)`\n```\n[b](b.md)\n```\nplain mention of c.md\n[d](d.md)";
+        let links = extract(note, false);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target, "d.md");
+        assert_eq!(links[0].line, 6);
+    }
+
+    #[test]
+    fn resolve_relative_wiki_and_extensionless() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        for f in ["a.md", "sub/b.md", "sub/c.org", "sub/paper.pdf"] {
+            std::fs::write(root.join(f), "").unwrap();
+        }
+        let files = note_files(root);
+        let source = root.join("sub/b.md");
+        let link = |kind, target: &str| Link {
+            kind,
+            target: target.to_string(),
+            anchor: None,
+            line: 1,
+            span: 0..target.len(),
+            text: None,
+        };
+
+        let nested = Resolver::new(root, &files, true);
+        let found = |l: &Link| nested.resolve(&source, l);
+        assert_eq!(
+            found(&link(LinkKind::Inline, "../a.md")),
+            Some(root.join("a.md"))
+        );
+        assert_eq!(
+            found(&link(LinkKind::Inline, "c")),
+            Some(root.join("sub/c.org"))
+        );
+        assert_eq!(found(&link(LinkKind::Wiki, "A")), Some(root.join("a.md")));
+        assert_eq!(found(&link(LinkKind::Inline, "missing.md")), None);
+        assert_eq!(found(&link(LinkKind::Inline, "https://x.org")), None);
+        // Other files are found, but aren't notes
+        let pdf = link(LinkKind::Inline, "paper.pdf");
+        assert_eq!(found(&pdf), None);
+        assert_eq!(nested.file(&source, &pdf), Some(root.join("sub/paper.pdf")));
+
+        let flat = Resolver::new(root, &files, false);
+        let link = link(LinkKind::Inline, "sub/b.md");
+        assert_eq!(flat.resolve(&source, &link), Some(source.clone()));
+    }
+}
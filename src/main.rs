@@ -1,14 +1,29 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod utils;
-use duct::cmd;
 use utils::fzf_choose;
 
 mod backlinks;
+mod check;
 mod config;
+mod editor;
+mod find;
+mod graph;
+mod links;
+mod mentions;
+mod metadata;
+mod mv;
+mod new;
+mod notes_map;
+mod orphans;
+mod outgoing;
+mod output;
+mod picker;
+mod semantic;
 mod tantivy_search;
+mod template;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -16,10 +31,18 @@ struct Cli {
     /// Optional name to operate on
     name: Option<String>,
 
-    /// Sets a custom config file
+    /// Sets a custom config file (default: $XDG_CONFIG_HOME/nt/config.toml)
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
 
+    /// Operate on a named vault from the config file
+    #[arg(long, value_name = "NAME", global = true)]
+    vault: Option<String>,
+
+    /// Output format for results, diagnostics always go to stderr
+    #[arg(long, value_enum, default_value_t = output::Format::Plain, global = true)]
+    format: output::Format,
+
     /// Turn debugging information on
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
@@ -27,7 +50,7 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Use FZF
+    /// Pick notes interactively, with a preview
     #[arg(short, long)]
     fzf: bool,
 }
@@ -50,18 +73,72 @@ enum Commands {
         /// Initialize the index
         #[arg(short, long)]
         init: bool,
+
+        /// Only print the paths of matching notes, without scores or lines
+        #[arg(short = 'l', long)]
+        paths_only: bool,
+
+        /// Combine keyword and semantic rankings
+        #[arg(long, conflicts_with = "sem")]
+        hybrid: bool,
+
+        /// Number of results
+        #[arg(short = 'n', long, default_value_t = 15)]
+        count: usize,
     },
 
-    /// Find a note by name
-    Find {},
+    /// Find a note by file name, title or alias, or pick one without a query
+    Find {
+        /// Matched fuzzily, exact and prefix matches rank first
+        query: Option<String>,
+
+        /// Number of results
+        #[arg(short = 'n', long, default_value_t = 15)]
+        count: usize,
+
+        /// Print the notes in absolute paths rather than relative
+        #[arg(short, long)]
+        absolute: bool,
+    },
 
     /// Add a new note
-    New {},
+    New {
+        /// The title of the note, also used for its file name
+        title: String,
+
+        /// Tags for the template, may be repeated
+        #[arg(short, long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+
+        /// Only create the note, without opening the editor
+        #[arg(long)]
+        no_edit: bool,
+
+        /// The name or file of the template, see `nt template list`
+        #[arg(short = 'T', long)]
+        template: Option<String>,
+
+        /// Set a template variable, may be repeated
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = template::parse_var)]
+        vars: Vec<(String, String)>,
+    },
 
     /// Backlinks
     Backlinks {
         file: Option<PathBuf>,
 
+        /// Only links to this heading, or to a block with `^id`
+        #[arg(long, value_name = "HEADING")]
+        heading: Option<String>,
+
+        /// Print each link with its text and the line it is on
+        #[arg(short, long)]
+        lines: bool,
+
+        /// Print NUM lines of context around each link, implies --lines
+        #[arg(short = 'C', long, value_name = "NUM")]
+        context: Option<usize>,
+
         /// Print the backlinks in absolute paths rather than relative
         #[arg(short, long)]
         absolute: bool,
@@ -71,11 +148,206 @@ enum Commands {
         nested: bool,
     },
 
-    /// Edit a note in Neovim
-    Edit {},
+    /// Outgoing links of a note
+    Links {
+        file: Option<PathBuf>,
+
+        /// Print the targets in absolute paths rather than relative
+        #[arg(short, long)]
+        absolute: bool,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+    },
+
+    /// Notes mentioning the title or aliases of a note without linking it
+    Mentions {
+        file: Option<PathBuf>,
+
+        /// Print the notes in absolute paths rather than relative
+        #[arg(short, long)]
+        absolute: bool,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+
+        /// Ask for each mention whether to turn it into a link
+        #[arg(long)]
+        apply: bool,
+    },
+
+    /// Notes that no other note links to
+    Orphans(UnlinkedArgs),
+
+    /// Notes that link to no other note
+    Deadends(UnlinkedArgs),
+
+    /// Move or rename a note and rewrite the links to it
+    Mv {
+        old: PathBuf,
+
+        /// The new path, or a directory to move the note into
+        new: PathBuf,
+
+        /// Print the changes as a diff without moving or writing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+    },
+
+    /// Export or analyse the link graph
+    Graph {
+        #[command(subcommand)]
+        graph: GraphCommands,
+    },
+
+    /// Check the vault for problems
+    Check {
+        #[command(subcommand)]
+        check: CheckCommands,
+    },
+
+    /// List and preview the templates for new notes
+    Template {
+        #[command(subcommand)]
+        template: TemplateCommands,
+    },
+
+    /// Edit a note in the default editor
+    Edit(OpenArgs),
 
     /// Open a note in VS Code
-    Open {},
+    Open(OpenArgs),
+}
+
+#[derive(Args)]
+struct OpenArgs {
+    /// The note, `FILE:LINE` or `FILE:LINE:COLUMN` also move the cursor.
+    /// Relative paths not under the current directory are taken from the notes directory.
+    /// Picked interactively if missing.
+    file: Option<String>,
+
+    /// Open the best search result at its first matching line
+    #[arg(short, long, value_name = "QUERY", conflicts_with = "file")]
+    search: Option<String>,
+
+    /// The line to put the cursor on
+    #[arg(short, long)]
+    line: Option<usize>,
+
+    /// The column to put the cursor on
+    #[arg(short, long, requires = "line")]
+    column: Option<usize>,
+}
+
+#[derive(Subcommand)]
+enum TemplateCommands {
+    /// Print the name and file of each template
+    List,
+
+    /// Print a template with its variables filled in, asking for any missing ones
+    Render {
+        /// The name or file of the template
+        name: String,
+
+        /// Set a template variable, may be repeated
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = template::parse_var)]
+        vars: Vec<(String, String)>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CheckCommands {
+    /// Report links to notes that don't exist, exits non-zero if there are any
+    Links {
+        /// Print the notes in absolute paths rather than relative
+        #[arg(short, long)]
+        absolute: bool,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum GraphCommands {
+    /// Print the notes and links, as DOT unless --format is graphml, json or jsonl
+    Export {
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+    },
+
+    /// Degrees, PageRank, components and clusters, listing hubs and islands
+    Stats {
+        #[command(flatten)]
+        scope: ScopeArgs,
+
+        /// Number of hubs and clusters to list
+        #[arg(short = 't', long, default_value_t = 10)]
+        top: usize,
+
+        /// Specify Notes are in nested heirarchy (default assumes flat directory)
+        #[arg(short, long)]
+        nested: bool,
+    },
+}
+
+#[derive(Args)]
+struct ScopeArgs {
+    /// Only notes under this directory, relative to the notes directory
+    #[arg(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+
+    /// Only notes near this one
+    #[arg(long, value_name = "FILE")]
+    around: Option<PathBuf>,
+
+    /// How many links away from --around to go, in either direction
+    #[arg(long, default_value_t = 1, requires = "around")]
+    hops: usize,
+}
+
+impl ScopeArgs {
+    fn scope(&self) -> graph::Scope {
+        graph::Scope {
+            dir: self.dir.clone(),
+            around: self.around.clone(),
+            hops: self.hops,
+        }
+    }
+}
+
+#[derive(Args)]
+struct UnlinkedArgs {
+    /// Only notes under this directory, relative to the notes directory
+    #[arg(long, value_name = "DIR")]
+    dir: Option<PathBuf>,
+
+    /// Only notes last modified more than DAYS days ago
+    #[arg(long, value_name = "DAYS")]
+    older_than: Option<u64>,
+
+    /// Only notes modified within the last DAYS days
+    #[arg(long, value_name = "DAYS")]
+    newer_than: Option<u64>,
+
+    /// Print the notes in absolute paths rather than relative
+    #[arg(short, long)]
+    absolute: bool,
+
+    /// Specify Notes are in nested heirarchy (default assumes flat directory)
+    #[arg(short, long)]
+    nested: bool,
 }
 
 fn main() {
@@ -85,16 +357,29 @@ fn main() {
 fn run() {
     let cli = Cli::parse();
 
-    let config = config::Config::default();
+    let config = match config::Config::load(cli.config.as_deref(), cli.vault.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
     let verbose = cli.debug > 0;
 
-    // You can check the value provided by positional arguments, or option arguments
-    if let Some(name) = cli.name.as_deref() {
-        println!("Value for name: {name}");
+    let exports_graph = matches!(
+        cli.command,
+        Some(Commands::Graph {
+            graph: GraphCommands::Export { .. }
+        })
+    );
+    if cli.format.is_graph() && !exports_graph {
+        eprintln!("Error: this --format is only supported by graph export");
+        std::process::exit(1);
     }
 
-    if let Some(config_path) = cli.config.as_deref() {
-        println!("Value for config: {}", config_path.display());
+    // You can check the value provided by positional arguments, or option arguments
+    if let Some(name) = cli.name.as_deref() {
+        eprintln!("Value for name: {name}");
     }
 
     // You can see how many times a particular flag or argument occurred
@@ -114,58 +399,242 @@ fn run() {
             reindex: r,
             query,
             init,
+            paths_only,
+            hybrid,
+            count,
         }) => {
+            let opts = tantivy_search::SearchOptions {
+                reindex: *r,
+                init: *init,
+                paths_only: *paths_only,
+                hybrid: *hybrid,
+                count: *count,
+                format: cli.format,
+            };
             if *s {
-                let sn = "Semantic Search";
-                if *r {
-                    println!("Reindexing the {sn}");
-                } else {
-                    println!("{sn}");
+                if let Some(q) = query {
+                    if let Err(e) = semantic::run(config, verbose, q, &opts) {
+                        eprintln!("Error: {e}");
+                        std::process::exit(1);
+                    }
                 }
             } else {
                 // TODO Make this approach the same for backlinks
                 if !cli.fzf {
                     if let Some(q) = query {
-                        tantivy_search::run(config, verbose, *r, q, *init);
-                        return;
+                        if let Err(e) = tantivy_search::run(config, verbose, q, &opts) {
+                            eprintln!("Error: {e}");
+                            std::process::exit(1);
+                        }
                     }
                 } else {
-                    if let Some(_) = query {
+                    if query.is_some() {
                         panic!("Cannot specify query with FZF");
                     }
-                    utils::fzf_search();
+                    utils::fzf_search(&config, verbose, *count);
                 }
             }
         }
-        Some(Commands::Find {}) => println!("Finding..."),
-        Some(Commands::New {}) => println!("Adding..."),
+        Some(Commands::Find {
+            query,
+            count,
+            absolute,
+        }) => match query {
+            Some(_) if cli.fzf => {
+                eprintln!("Error: Cannot specify query with FZF");
+                std::process::exit(1);
+            }
+            Some(q) => find::run(config, q, *count, *absolute, cli.format),
+            None => println!("{}", fzf_choose(&config.note_taking_dir).display()),
+        },
+        Some(Commands::New {
+            title,
+            tags,
+            no_edit,
+            template,
+            vars,
+        }) => {
+            if let Err(e) = new::run(config, title, tags, template.as_deref(), vars, !no_edit) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
         Some(Commands::Backlinks {
             file,
+            heading,
+            lines,
+            context,
             absolute,
             nested,
         }) => {
-            let f: PathBuf;
-            if !cli.fzf {
-                f = match file {
-                    Some(_) => panic!("Cannot specify file with FZF"),
-                    None => fzf_choose(config.note_taking_dir.as_str()),
-                }
-            } else {
-                f = match file {
-                    Some(f) => f.clone(),
-                    None => fzf_choose(config.note_taking_dir.as_str()),
-                }
+            let f = choose_file(&cli, file, &config);
+            let opts = backlinks::BacklinkOptions {
+                heading: heading.clone(),
+                context: context.or(lines.then_some(0)),
+                absolute: *absolute,
+                nested: *nested,
+            };
+            backlinks::run(config, &f, &opts, verbose, cli.format)
+        }
+        Some(Commands::Links {
+            file,
+            absolute,
+            nested,
+        }) => {
+            let f = choose_file(&cli, file, &config);
+            outgoing::run(config, &f, *absolute, *nested, verbose, cli.format)
+        }
+        Some(Commands::Mentions {
+            file,
+            absolute,
+            nested,
+            apply,
+        }) => {
+            let f = choose_file(&cli, file, &config);
+            let result = mentions::run(config, &f, *absolute, *nested, *apply, verbose, cli.format);
+            if let Err(e) = result {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Orphans(args)) => {
+            unlinked(config, orphans::Unlinked::Orphan, args, verbose, cli.format)
+        }
+        Some(Commands::Deadends(args)) => unlinked(
+            config,
+            orphans::Unlinked::DeadEnd,
+            args,
+            verbose,
+            cli.format,
+        ),
+        Some(Commands::Mv {
+            old,
+            new,
+            dry_run,
+            nested,
+        }) => {
+            if let Err(e) = mv::run(config, old, new, *nested, *dry_run, verbose, cli.format) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Graph {
+            graph: GraphCommands::Export { scope, nested },
+        }) => graph::export(config, &scope.scope(), *nested, verbose, cli.format),
+        Some(Commands::Graph {
+            graph: GraphCommands::Stats { scope, top, nested },
+        }) => graph::stats(config, &scope.scope(), *top, *nested, verbose, cli.format),
+        Some(Commands::Check {
+            check: CheckCommands::Links { absolute, nested },
+        }) => {
+            let ok = check::links(config, *absolute, *nested, verbose, cli.format);
+            if !ok {
+                std::process::exit(1);
             }
-            backlinks::run(config, &f, *absolute, *nested, cli.debug > 0)
         }
-        Some(Commands::Edit {}) => println!("Editing..."),
-        Some(Commands::Open {}) => println!("Opening..."),
+        Some(Commands::Template {
+            template: TemplateCommands::List,
+        }) => template::list(config, cli.format),
+        Some(Commands::Template {
+            template: TemplateCommands::Render { name, vars },
+        }) => {
+            if let Err(e) = template::preview(config, name, vars) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Edit(args)) => {
+            let editor = editor::command(&config, |k| std::env::var(k).ok());
+            edit(&cli, &config, &editor, args, verbose);
+        }
+        Some(Commands::Open(args)) => edit(&cli, &config, &config.vscode_path, args, verbose),
         None => {}
     }
 
     // Continued program logic goes here...
 }
 
+/// The file given on the command line, else one picked interactively
+fn choose_file(cli: &Cli, file: &Option<PathBuf>, config: &config::Config) -> PathBuf {
+    if cli.fzf {
+        match file {
+            Some(_) => panic!("Cannot specify file with FZF"),
+            None => fzf_choose(config.note_taking_dir.as_str()),
+        }
+    } else {
+        match file {
+            Some(f) => f.clone(),
+            None => fzf_choose(config.note_taking_dir.as_str()),
+        }
+    }
+}
+
+/// Open the note named by `args` with `editor`
+fn edit(cli: &Cli, config: &config::Config, editor: &str, args: &OpenArgs, verbose: bool) {
+    let (file, position) = match (&args.file, &args.search) {
+        (_, Some(query)) => match tantivy_search::best_hit(config, verbose, query) {
+            Ok(Some(hit)) => {
+                let line = hit.lines.first().map(|l| editor::Position {
+                    line: l.number,
+                    column: None,
+                });
+                (PathBuf::from(hit.path), line)
+            }
+            Ok(None) => {
+                eprintln!("Error: no notes match {query:?}");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        (Some(file), None) => {
+            let (file, position) = editor::parse_position(file);
+            let in_notes = Path::new(&config.note_taking_dir).join(&file);
+            match file.is_relative() && !file.exists() && in_notes.exists() {
+                true => (in_notes, position),
+                false => (file, position),
+            }
+        }
+        (None, None) => (choose_file(cli, &None, config), None),
+    };
+    let position = match args.line {
+        Some(line) => Some(editor::Position {
+            line,
+            column: args.column,
+        }),
+        None => position,
+    };
+    if let Err(e) = editor::open_at(editor, &file, position) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn unlinked(
+    config: config::Config,
+    kind: orphans::Unlinked,
+    args: &UnlinkedArgs,
+    verbose: bool,
+    format: output::Format,
+) {
+    let filter = orphans::Filter {
+        dir: args.dir.clone(),
+        older_than: args.older_than,
+        newer_than: args.newer_than,
+    };
+    orphans::run(
+        config,
+        kind,
+        &filter,
+        args.absolute,
+        args.nested,
+        verbose,
+        format,
+    )
+}
+
 // TODO
 // Search maybe should have optional query
 // for the reindex and reinit
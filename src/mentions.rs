@@ -0,0 +1,291 @@
+use crate::backlinks::{display_path, relative_path};
+use crate::config::Config;
+use crate::links;
+use crate::metadata;
+use crate::notes_map;
+use crate::output::{self, Format, Record};
+use crate::semantic;
+use crate::tantivy_search;
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A `Record` for a mention, `path` is the note containing it
+#[derive(Debug, Serialize)]
+struct MentionRecord {
+    #[serde(flatten)]
+    record: Record,
+    /// The mentioned name as written
+    mention: String,
+}
+
+/// A name of the note in the text of another
+#[derive(Debug, PartialEq)]
+struct Mention {
+    /// 1-based line number
+    line: usize,
+    /// Byte range within the line
+    range: Range<usize>,
+}
+
+/// List the notes mentioning `file`'s title or aliases without linking it,
+/// with `apply` ask which mentions to turn into links
+pub fn run(
+    config: Config,
+    file: &Path,
+    absolute: bool,
+    nested: bool,
+    apply: bool,
+    verbose: bool,
+    format: Format,
+) -> io::Result<()> {
+    let target = file
+        .canonicalize()
+        .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", file));
+    let notes_dir = notes_map::notes_dir(&config);
+
+    let names = names(&target);
+    let Some(pattern) = pattern(&names) else {
+        return Err(io::Error::other(format!(
+            "{} has no title or aliases to look for",
+            file.display()
+        )));
+    };
+    let indexed = tantivy_search::phrase_search(&config, verbose, &names)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .filter_map(|p| p.canonicalize().ok());
+    // The search index only holds markdown notes, org notes are all read
+    let org = links::note_files(&notes_dir)
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "org"));
+    let mut sources: Vec<PathBuf> = indexed.chain(org).filter(|p| *p != target).collect();
+    sources.sort();
+    sources.dedup();
+
+    let mut records = vec![];
+    let mut answers = io::stdin().lock().lines();
+    let mut link_all = false;
+    let mut linked = 0;
+    'sources: for source in &sources {
+        let Ok(content) = fs::read_to_string(source) else {
+            continue;
+        };
+        let org = source.extension().is_some_and(|e| e == "org");
+        let shown = display_path(&notes_dir, source, absolute);
+        let lines: Vec<&str> = content.lines().collect();
+        let mut accepted = vec![];
+        for mention in find(&content, &pattern, org) {
+            let text = lines[mention.line - 1];
+            if !apply && format != Format::Plain {
+                let mut record = Record::new(shown.clone());
+                record.snippet = Some(text.to_string());
+                record.line = Some(mention.line);
+                records.push(MentionRecord {
+                    record,
+                    mention: text[mention.range.clone()].to_string(),
+                });
+                continue;
+            }
+            println!("{}", grep_line(&shown, text, &mention));
+            if !apply {
+                continue;
+            }
+            if !link_all {
+                eprint!(
+                    "Link {:?}? [y]es/[n]o/[a]ll/[q]uit ",
+                    &text[mention.range.clone()]
+                );
+                io::stderr().flush()?;
+                let answer = answers.next().transpose()?.unwrap_or_else(|| "q".into());
+                match answer.trim() {
+                    "y" | "yes" => {}
+                    "a" | "all" => link_all = true,
+                    "q" | "quit" => {
+                        write_links(source, &content, &accepted, &target, &notes_dir, nested)?;
+                        linked += accepted.len();
+                        break 'sources;
+                    }
+                    _ => continue,
+                }
+            }
+            accepted.push(mention);
+        }
+        write_links(source, &content, &accepted, &target, &notes_dir, nested)?;
+        linked += accepted.len();
+    }
+    output::print_json(&records, format);
+
+    if apply {
+        eprintln!("Linked {linked} mentions");
+        if linked > 0 {
+            tantivy_search::update_index(&config, verbose).map_err(io::Error::other)?;
+            semantic::update_store(&config, verbose)?;
+        }
+    }
+    Ok(())
+}
+
+/// The title and aliases of a note, else its file name
+fn names(note: &Path) -> Vec<String> {
+    let content = fs::read_to_string(note).unwrap_or_default();
+    let meta = metadata::parse(&content);
+    let mut names: Vec<String> = meta.title.into_iter().chain(meta.aliases).collect();
+    if names.is_empty() {
+        names.extend(note.file_stem().map(|s| s.to_string_lossy().into_owned()));
+    }
+    names.retain(|n| !n.trim().is_empty());
+    names
+}
+
+/// Any of the names as a whole word, ignoring case, the longest first
+fn pattern(names: &[String]) -> Option<Regex> {
+    let mut names: Vec<&str> = names.iter().map(|n| n.trim()).collect();
+    names.sort_by_key(|n| std::cmp::Reverse(n.len()));
+    let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let alternatives: Vec<String> = names
+        .iter()
+        .map(|n| {
+            let start = if word(n.chars().next()) { r"\b" } else { "" };
+            let end = if word(n.chars().last()) { r"\b" } else { "" };
+            format!("{start}{}{end}", regex::escape(n))
+        })
+        .collect();
+    if alternatives.is_empty() {
+        return None;
+    }
+    Regex::new(&format!("(?i){}", alternatives.join("|"))).ok()
+}
+
+/// Mentions in prose, outside frontmatter, code, links and URLs
+fn find(content: &str, pattern: &Regex, org: bool) -> Vec<Mention> {
+    let (frontmatter, _) = metadata::split_frontmatter(content);
+    // The frontmatter plus its two `---` lines
+    let skip = frontmatter.map_or(0, |f| f.lines().count() + 2);
+    let mut mentions = vec![];
+    let mut in_fence = false;
+    for (i, line) in content.lines().enumerate().skip(skip) {
+        let trimmed = line.trim_start().to_lowercase();
+        let fence = match org {
+            true => trimmed.starts_with("#+begin_src") || trimmed.starts_with("#+end_src"),
+            false => trimmed.starts_with("```") || trimmed.starts_with("~~~"),
+        };
+        if fence {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let excluded = links::non_prose(line);
+        for m in pattern.find_iter(line) {
+            let overlaps = excluded
+                .iter()
+                .any(|r| r.start < m.end() && m.start() < r.end);
+            if !overlaps {
+                mentions.push(Mention {
+                    line: i + 1,
+                    range: m.range(),
+                });
+            }
+        }
+    }
+    mentions
+}
+
+/// `file:line:column: text` like `grep --column`, so that several mentions
+/// on a line can be told apart
+fn grep_line(shown: &str, text: &str, mention: &Mention) -> String {
+    let column = text[..mention.range.start].chars().count() + 1;
+    format!("{shown}:{}:{column}: {text}", mention.line)
+}
+
+/// Replace the mentions in `source` with links to `target`
+fn write_links(
+    source: &Path,
+    content: &str,
+    mentions: &[Mention],
+    target: &Path,
+    notes_dir: &Path,
+    nested: bool,
+) -> io::Result<()> {
+    if mentions.is_empty() {
+        return Ok(());
+    }
+    let path = match nested {
+        true => relative_path(source.parent().unwrap_or(notes_dir), target),
+        false => relative_path(notes_dir, target),
+    };
+    let org = source.extension().is_some_and(|e| e == "org");
+    fs::write(source, link_mentions(content, mentions, &path, org))
+}
+
+fn link_mentions(content: &str, mentions: &[Mention], path: &str, org: bool) -> String {
+    let mut lines: Vec<String> = content.split_inclusive('\n').map(String::from).collect();
+    // Later mentions first, so the earlier ranges stay valid
+    for mention in mentions.iter().rev() {
+        let line = &mut lines[mention.line - 1];
+        let text = &line[mention.range.clone()];
+        let link = match org {
+            true => format!("[[file:{path}][{text}]]"),
+            false => format!("[{text}]({})", path.replace('%', "%25").replace(' ', "%20")),
+        };
+        line.replace_range(mention.range.clone(), &link);
+    }
+    lines.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unlinked_mentions_in_prose() {
+        let note = "---\ntitle: about rust\n---\nRust and RUST, but not rusty\n\
+                    [Rust](rust.md) [[rust]] `rust` https://rust.org\n\
+                    ```\nrust\n```\nthe rust lang\n";
+        let names = ["Rust".to_string(), "Rust Lang".to_string()];
+        let mentions = find(note, &pattern(&names).unwrap(), false);
+        let found: Vec<(usize, &str)> = mentions
+            .iter()
+            .map(|m| {
+                (
+                    m.line,
+                    &note.lines().nth(m.line - 1).unwrap()[m.range.clone()],
+                )
+            })
+            .collect();
+        assert_eq!(found, vec![(4, "Rust"), (4, "RUST"), (9, "rust lang")]);
+
+        let linked = link_mentions(note, &mentions[1..], "my notes/rust.md", false);
+        assert!(linked.contains("Rust and [RUST](my%20notes/rust.md), but"));
+        assert!(linked.ends_with("the [rust lang](my%20notes/rust.md)\n"));
+    }
+
+    #[test]
+    fn mentions_on_one_line_have_their_column() {
+        let text = "Rust Notes mention ferris";
+        let names = ["Rust Notes".to_string(), "ferris".to_string()];
+        let shown: Vec<String> = find(text, &pattern(&names).unwrap(), false)
+            .iter()
+            .map(|m| grep_line("a.md", text, m))
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                "a.md:1:1: Rust Notes mention ferris",
+                "a.md:1:20: Rust Notes mention ferris"
+            ]
+        );
+    }
+
+    #[test]
+    fn names_are_whole_words() {
+        let pattern = pattern(&["C++".to_string()]).unwrap();
+        assert!(pattern.is_match("I like c++."));
+        assert!(!pattern.is_match("abc++"));
+    }
+}
@@ -0,0 +1,183 @@
+use regex::Regex;
+use serde_yaml::Value;
+use std::sync::LazyLock;
+
+/// Metadata extracted from a markdown note
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NoteMeta {
+    /// Frontmatter `title`, else the first H1
+    pub title: Option<String>,
+    /// Text of every ATX heading, in order
+    pub headings: Vec<String>,
+    /// Frontmatter `tags` and inline `#tags`, lowercase and without the `#`
+    pub tags: Vec<String>,
+    /// Frontmatter `aliases` (or `alias`), other names the note goes by
+    pub aliases: Vec<String>,
+}
+
+static HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(#{1,6})\s+(.*?)\s*#*\s*$").expect("Failed to create regex"));
+
+// A tag starts after whitespace or at the start of a line, `#` followed by a space is a heading
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?:^|\s)#([\w/-]+)").expect("Failed to create regex"));
+
+pub fn parse(content: &str) -> NoteMeta {
+    let (frontmatter, body) = split_frontmatter(content);
+    let mut meta = NoteMeta::default();
+    let mut h1 = None;
+
+    if let Some(fm) = frontmatter {
+        if let Ok(Value::Mapping(map)) = serde_yaml::from_str::<Value>(fm) {
+            meta.title = map
+                .get("title")
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            if let Some(tags) = map.get("tags") {
+                meta.tags.extend(string_list(tags));
+            }
+            if let Some(aliases) = map.get("aliases").or_else(|| map.get("alias")) {
+                meta.aliases = alias_list(aliases);
+            }
+        }
+    }
+
+    for line in body_lines(body) {
+        if let Some(caps) = HEADING.captures(line) {
+            let text = caps[2].to_string();
+            if h1.is_none() && &caps[1] == "#" {
+                h1 = Some(text.clone());
+            }
+            meta.headings.push(text);
+            continue;
+        }
+        for caps in TAG.captures_iter(line) {
+            let tag = &caps[1];
+            // `#1` is an issue number rather than a tag
+            if !tag.chars().all(|c| c.is_ascii_digit()) {
+                meta.tags.push(tag.to_string());
+            }
+        }
+    }
+
+    meta.title = meta.title.or(h1);
+    meta.tags = normalize_tags(meta.tags);
+    meta
+}
+
+/// Split `---` delimited YAML frontmatter from the body
+pub fn split_frontmatter(content: &str) -> (Option<&str>, &str) {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return (None, content);
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == "---" || trimmed == "..." {
+            return (Some(&rest[..offset]), &rest[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, content)
+}
+
+/// Lines of the body outside fenced code blocks
+fn body_lines(body: &str) -> impl Iterator<Item = &str> {
+    let mut in_fence = false;
+    body.lines().filter(move |line| {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            return false;
+        }
+        !in_fence
+    })
+}
+
+/// Frontmatter lists may be a YAML sequence or a comma / space separated string
+pub fn string_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::Sequence(seq) => seq
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(str::to_string)
+            .collect(),
+        Value::String(s) => s
+            .split([',', ' '])
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Like `string_list`, but names may contain spaces
+fn alias_list(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => string_list(value),
+    }
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|t| t.trim().trim_start_matches('#').to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frontmatter_title_and_tags() {
+        let note = "---\ntitle: Rust Notes\ntags: [Meeting, rust]\n---\n# Heading\nbody #todo\n";
+        let meta = parse(note);
+        assert_eq!(meta.title.as_deref(), Some("Rust Notes"));
+        assert_eq!(meta.headings, vec!["Heading"]);
+        assert_eq!(meta.tags, vec!["meeting", "rust", "todo"]);
+    }
+
+    #[test]
+    fn title_falls_back_to_first_h1() {
+        let note = "Intro\n## Setup\n# Main Title\n# Second\n";
+        let meta = parse(note);
+        assert_eq!(meta.title.as_deref(), Some("Main Title"));
+        assert_eq!(meta.headings, vec!["Setup", "Main Title", "Second"]);
+    }
+
+    #[test]
+    fn code_blocks_and_issue_numbers_are_not_tags() {
+        let note = "see #12 and #project/nt\n```sh\n# comment\necho #notatag\n```\n";
+        let meta = parse(note);
+        assert_eq!(meta.tags, vec!["project/nt"]);
+        assert!(meta.headings.is_empty());
+    }
+
+    #[test]
+    fn aliases_keep_spaces() {
+        let note = "---\naliases: [Rust Lang, rustlang]\n---\n";
+        assert_eq!(parse(note).aliases, vec!["Rust Lang", "rustlang"]);
+        let note = "---\nalias: Rust Lang, Ferris\n---\n";
+        assert_eq!(parse(note).aliases, vec!["Rust Lang", "Ferris"]);
+    }
+
+    #[test]
+    fn string_tags_in_frontmatter() {
+        let note = "---\ntags: \"#a, b c\"\n---\n";
+        assert_eq!(parse(note).tags, vec!["a", "b", "c"]);
+    }
+}
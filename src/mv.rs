@@ -0,0 +1,398 @@
+use crate::backlinks::relative_path;
+use crate::config::Config;
+use crate::links::{self, LinkKind};
+use crate::notes_map::{self, NotesMap, ResolvedLink};
+use crate::output::{self, Format, Record};
+use crate::semantic;
+use crate::tantivy_search;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A note whose links were rewritten
+#[derive(Debug)]
+struct Change {
+    /// The note before the move
+    path: PathBuf,
+    /// Line number and new line
+    lines: Vec<(usize, String)>,
+    /// The content before and after the rewrite
+    before: String,
+    content: String,
+}
+
+/// Rewrites links for moving `old` to `new`, both absolute
+struct Rename<'a> {
+    notes_dir: &'a Path,
+    old: &'a Path,
+    new: &'a Path,
+    nested: bool,
+}
+
+/// Move a note and rewrite every link to it, with `dry_run` only print the
+/// changes as a diff
+pub fn run(
+    config: Config,
+    old: &Path,
+    new: &Path,
+    nested: bool,
+    dry_run: bool,
+    verbose: bool,
+    format: Format,
+) -> io::Result<()> {
+    let notes_dir = notes_map::notes_dir(&config);
+    let old = old
+        .canonicalize()
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", old.display())))?;
+    let new = destination(&old, new)?;
+    for path in [&old, &new] {
+        if !path.starts_with(&notes_dir) || !links::is_note(path) {
+            return Err(io::Error::other(format!(
+                "{} is not a note in {}",
+                path.display(),
+                notes_dir.display()
+            )));
+        }
+    }
+    if new.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", new.display()),
+        ));
+    }
+
+    let map = NotesMap::load(&notes_dir, &notes_map::cache_file(&config), nested, verbose);
+    let rename = Rename {
+        notes_dir: &notes_dir,
+        old: &old,
+        new: &new,
+        nested,
+    };
+    let changes = rename.changes(&map);
+
+    let shown = |path: &Path| relative_path(&notes_dir, path);
+    if dry_run {
+        match format {
+            Format::Plain => print!("{}", diff(&changes, &rename, shown)),
+            _ => print_records(&changes, &rename, shown, format),
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = new.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::rename(&old, &new)?;
+    for change in &changes {
+        let path = rename.after(&change.path);
+        fs::write(path, &change.content)?;
+    }
+    print_records(&changes, &rename, shown, format);
+    let count: usize = changes.iter().map(|c| c.lines.len()).sum();
+    eprintln!(
+        "Moved {} to {}, rewrote {count} lines in {} notes",
+        shown(&old),
+        shown(&new),
+        changes.len()
+    );
+
+    tantivy_search::update_index(&config, verbose).map_err(io::Error::other)?;
+    semantic::update_store(&config, verbose)
+}
+
+/// Like `mv`, a directory destination keeps the file name
+fn destination(old: &Path, new: &Path) -> io::Result<PathBuf> {
+    let into_dir = new.is_dir() || new.as_os_str().to_string_lossy().ends_with('/');
+    let new = match into_dir {
+        true => new.join(old.file_name().unwrap_or_default()),
+        false => new.to_path_buf(),
+    };
+    let new = std::path::absolute(new)?;
+    Ok(links::normalize(&new))
+}
+
+impl Rename<'_> {
+    /// Where `path` is after the move
+    fn after<'p>(&'p self, path: &'p Path) -> &'p Path {
+        match path == self.old {
+            true => self.new,
+            false => path,
+        }
+    }
+
+    /// The notes whose content changes, ordered by path
+    fn changes(&self, map: &NotesMap) -> Vec<Change> {
+        let mut changes = vec![];
+        for (source, note) in map.notes() {
+            let mut edits: Vec<(usize, std::ops::Range<usize>, String)> = note
+                .links
+                .iter()
+                .filter_map(|l| Some((l.link.line, l.link.span.clone(), self.rewrite(source, l)?)))
+                .collect();
+            if edits.is_empty() {
+                continue;
+            }
+            // Later spans first, so the earlier ones stay valid
+            edits.sort_by_key(|(line, span, _)| std::cmp::Reverse((*line, span.start)));
+
+            let Ok(content) = fs::read_to_string(source) else {
+                eprintln!("Unable to read {:?}, its links are not rewritten", source);
+                continue;
+            };
+            let mut lines: Vec<String> = content.split_inclusive('\n').map(String::from).collect();
+            let mut changed = vec![];
+            for (number, span, text) in edits {
+                let Some(line) = lines.get_mut(number - 1) else {
+                    continue;
+                };
+                if line.get(span.clone()) == Some(text.as_str()) {
+                    continue;
+                }
+                if changed.last().is_none_or(|(n, _)| *n != number) {
+                    changed.push((number, String::new()));
+                }
+                line.replace_range(span, &text);
+            }
+            if changed.is_empty() {
+                continue;
+            }
+            for (number, new) in changed.iter_mut() {
+                *new = lines[*number - 1].trim_end().to_string();
+            }
+            changed.reverse();
+            changes.push(Change {
+                path: source.to_path_buf(),
+                lines: changed,
+                before: content,
+                content: lines.concat(),
+            });
+        }
+        changes
+    }
+
+    /// The new text for the target of a link in `source`, if it changes
+    fn rewrite(&self, source: &Path, resolved: &ResolvedLink) -> Option<String> {
+        let link = &resolved.link;
+        let target = resolved
+            .target
+            .as_deref()
+            .or(resolved.attachment.as_deref())?;
+        let moved_target = target == self.old;
+        // A moved note's own relative links only break if they are relative to it
+        let moved_source = source == self.old && self.nested && link.kind != LinkKind::Wiki;
+        if !moved_target && !moved_source {
+            return None;
+        }
+        let (source, target) = (self.after(source), self.after(target));
+
+        let written = Path::new(&link.target);
+        let keep_extension = links::is_note(written) || resolved.attachment.is_some();
+        let text = match link.kind {
+            // Unchanged names are skipped by `changes`
+            LinkKind::Wiki if !link.target.contains('/') => {
+                let name = match keep_extension {
+                    true => target.file_name(),
+                    false => target.file_stem(),
+                };
+                return Some(name?.to_string_lossy().into_owned());
+            }
+            LinkKind::Wiki => relative_path(self.notes_dir, target),
+            _ if written.is_absolute() => target.display().to_string(),
+            _ => match self.nested {
+                true => relative_path(source.parent()?, target),
+                false => relative_path(self.notes_dir, target),
+            },
+        };
+        let text = match keep_extension {
+            true => text,
+            false => Path::new(&text).with_extension("").display().to_string(),
+        };
+        // Markdown link targets can't contain spaces, org and wiki links can
+        Some(match link.kind {
+            LinkKind::Inline | LinkKind::Reference => text.replace('%', "%25").replace(' ', "%20"),
+            LinkKind::Wiki | LinkKind::Org => text,
+        })
+    }
+}
+
+/// Lines of context around each change in a diff
+const CONTEXT: usize = 3;
+
+/// The changes as a git diff, which `git apply` and `patch` can apply. The
+/// moved note's own changes go with the rename.
+fn diff(changes: &[Change], rename: &Rename, shown: impl Fn(&Path) -> String) -> String {
+    let (old, new) = (shown(rename.old), shown(rename.new));
+    let mut diff = format!("diff --git a/{old} b/{new}\nrename from {old}\nrename to {new}\n");
+    let (moved, others): (Vec<&Change>, Vec<&Change>) =
+        changes.iter().partition(|c| c.path == rename.old);
+    for change in moved.into_iter().chain(others) {
+        let (before, after) = (shown(&change.path), shown(rename.after(&change.path)));
+        if change.path != rename.old {
+            diff.push_str(&format!("diff --git a/{before} b/{after}\n"));
+        }
+        diff.push_str(&format!("--- a/{before}\n+++ b/{after}\n"));
+        diff.push_str(&hunks(&change.before, &change.content));
+    }
+    diff
+}
+
+/// Unified diff hunks between two versions of a note with the same number of
+/// lines, changes closer than twice the context share a hunk
+fn hunks(before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.split_inclusive('\n').collect();
+    let new: Vec<&str> = after.split_inclusive('\n').collect();
+    let changed: Vec<usize> = (0..old.len().min(new.len()))
+        .filter(|&i| old[i] != new[i])
+        .collect();
+
+    // 0-based, inclusive line ranges
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for &i in &changed {
+        let (start, end) = (i.saturating_sub(CONTEXT), (i + CONTEXT).min(new.len() - 1));
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let line = |prefix: char, text: &str| match text.strip_suffix('\n') {
+        Some(text) => format!("{prefix}{text}\n"),
+        None => format!("{prefix}{text}\n\\ No newline at end of file\n"),
+    };
+    let mut hunks = String::new();
+    for (start, end) in ranges {
+        let count = end - start + 1;
+        hunks.push_str(&format!("@@ -{0},{count} +{0},{count} @@\n", start + 1));
+        for i in start..=end {
+            if changed.contains(&i) {
+                hunks.push_str(&line('-', old[i]));
+                hunks.push_str(&line('+', new[i]));
+            } else {
+                hunks.push_str(&line(' ', new[i]));
+            }
+        }
+    }
+    hunks
+}
+
+fn print_records(
+    changes: &[Change],
+    rename: &Rename,
+    shown: impl Fn(&Path) -> String,
+    format: Format,
+) {
+    let mut records = vec![];
+    for change in changes {
+        for (number, new_line) in &change.lines {
+            let mut record = Record::new(shown(rename.after(&change.path)));
+            record.snippet = Some(new_line.clone());
+            record.line = Some(*number);
+            records.push(record);
+        }
+    }
+    output::print_json(&records, format);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlinks::relpath;
+
+    fn rewritten(nested: bool, new: &str) -> Vec<(String, String)> {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.md"), "# A\n[b](sub/b.md) [x](https://x.y)").unwrap();
+        std::fs::write(
+            root.join("sub/b.md"),
+            "[[a]] [[sub/b#Top|me]] [a](../a#A) [a](../a.md) [i](img.png)",
+        )
+        .unwrap();
+        std::fs::write(root.join("sub/img.png"), "").unwrap();
+        std::fs::write(root.join("c.org"), "[[file:sub/b.md::*Top][b]]\n").unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), nested, false);
+        let new = root.join(new);
+        let rename = Rename {
+            notes_dir: &root,
+            old: &root.join("sub/b.md"),
+            new: &new,
+            nested,
+        };
+        rename
+            .changes(&map)
+            .into_iter()
+            .map(|c| (relpath(&root, &c.path), c.content))
+            .collect()
+    }
+
+    #[test]
+    fn links_to_a_moved_note_are_rewritten() {
+        assert_eq!(
+            rewritten(false, "my notes/b 2.md"),
+            vec![
+                (
+                    "a.md".to_string(),
+                    "# A\n[b](my%20notes/b%202.md) [x](https://x.y)".to_string()
+                ),
+                (
+                    "c.org".to_string(),
+                    "[[file:my notes/b 2.md::*Top][b]]\n".to_string()
+                ),
+                (
+                    "sub/b.md".to_string(),
+                    "[[a]] [[my notes/b 2#Top|me]] [a](../a#A) [a](../a.md) [i](img.png)"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_links_follow_the_moved_note() {
+        assert_eq!(
+            rewritten(true, "b.md"),
+            vec![
+                (
+                    "a.md".to_string(),
+                    "# A\n[b](b.md) [x](https://x.y)".to_string()
+                ),
+                ("c.org".to_string(), "[[file:b.md::*Top][b]]\n".to_string()),
+                (
+                    "sub/b.md".to_string(),
+                    "[[a]] [[b#Top|me]] [a](a#A) [a](a.md) [i](sub/img.png)".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_runs_print_a_git_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("a.md"), "# A\n[b](b.md)\n").unwrap();
+        std::fs::write(root.join("b.md"), "[a](a.md)\nsee [[b]]").unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+        let new = root.join("c.md");
+        let rename = Rename {
+            notes_dir: &root,
+            old: &root.join("b.md"),
+            new: &new,
+            nested: false,
+        };
+        let shown = |path: &Path| relative_path(&root, path);
+        assert_eq!(
+            diff(&rename.changes(&map), &rename, shown),
+            "diff --git a/b.md b/c.md\nrename from b.md\nrename to c.md\n\
+             --- a/b.md\n+++ b/c.md\n\
+             @@ -1,2 +1,2 @@\n [a](a.md)\n\
+             -see [[b]]\n\\ No newline at end of file\n\
+             +see [[c]]\n\\ No newline at end of file\n\
+             diff --git a/a.md b/a.md\n--- a/a.md\n+++ b/a.md\n\
+             @@ -1,2 +1,2 @@\n # A\n-[b](b.md)\n+[b](c.md)\n"
+        );
+    }
+}
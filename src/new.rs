@@ -0,0 +1,168 @@
+use crate::config::{Config, FilenameScheme};
+use crate::editor;
+use crate::template;
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Create a note titled `title` from a template and open it in the editor.
+///
+/// The template is `template`, else `new_template`, else the template named
+/// `default`, else the built-in one.
+pub fn run(
+    config: Config,
+    title: &str,
+    tags: &[String],
+    template: Option<&str>,
+    vars: &[(String, String)],
+    edit: bool,
+) -> io::Result<()> {
+    let template = match template.or(config.new_template.as_deref()) {
+        Some(name) => template::load(&config, name)?,
+        None => template::load(&config, "default")
+            .unwrap_or_else(|_| template::DEFAULT_NOTE.to_string()),
+    };
+    let now = Local::now();
+    let mut variables = variables(&config, title, tags, &now);
+    variables.extend(vars.iter().cloned());
+    let name = filename(config.filename_scheme, title, &now);
+    let content = template::render(&template, &variables, &now, template::ask_stdin);
+    let path = create(Path::new(&config.note_taking_dir), &name, &content)?;
+
+    println!("{}", path.display());
+    if edit {
+        editor::open(&config, &path)?;
+    }
+    Ok(())
+}
+
+/// The variables available to templates of new notes, each tag is quoted so
+/// that `[{{tags}}]` is a YAML list
+fn variables(
+    config: &Config,
+    title: &str,
+    tags: &[String],
+    now: &DateTime<Local>,
+) -> HashMap<String, String> {
+    let mut variables = template::variables(config, now);
+    let tags: Vec<String> = tags.iter().map(|t| template::yaml_quote(t)).collect();
+    variables.extend([
+        ("title".to_string(), title.to_string()),
+        ("slug".to_string(), slug(title)),
+        ("tags".to_string(), tags.join(", ")),
+    ]);
+    variables
+}
+
+/// A Zettelkasten ID, the time to the second
+pub fn id(now: &DateTime<Local>) -> String {
+    now.format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Lowercase words joined by `-`
+pub fn slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The file name of a new note, titles without any letters or digits get an ID
+fn filename(scheme: FilenameScheme, title: &str, now: &DateTime<Local>) -> String {
+    let slug = slug(title);
+    let stem = match scheme {
+        FilenameScheme::Slug if !slug.is_empty() => slug,
+        FilenameScheme::IdSlug if !slug.is_empty() => format!("{}-{slug}", id(now)),
+        _ => id(now),
+    };
+    format!("{stem}.md")
+}
+
+/// Write a new note, an existing file is never overwritten
+fn create(dir: &Path, name: &str, content: &str) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                io::Error::new(e.kind(), format!("{} already exists", path.display()))
+            }
+            _ => e,
+        })?;
+    file.write_all(content.as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+
+    #[test]
+    fn filenames_follow_the_scheme() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let name = |scheme, title| filename(scheme, title, &now);
+        assert_eq!(
+            name(FilenameScheme::Slug, "Rust's Borrow Checker!"),
+            "rust-s-borrow-checker.md"
+        );
+        assert_eq!(name(FilenameScheme::Id, "Rust"), "20240131093005.md");
+        assert_eq!(
+            name(FilenameScheme::IdSlug, "Rust"),
+            "20240131093005-rust.md"
+        );
+        assert_eq!(name(FilenameScheme::Slug, "?!"), "20240131093005.md");
+    }
+
+    #[test]
+    fn new_notes_are_rendered_and_never_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let config = Config {
+            author: Some("ann".to_string()),
+            ..Config::default()
+        };
+        let tags = ["lang".to_string(), "cli".to_string()];
+        let vars = variables(&config, "Rust", &tags, &now);
+        let content = template::render(template::DEFAULT_NOTE, &vars, &now, |_| None);
+        assert!(content.contains("date: 2024-01-31\nauthor: \"ann\"\ntags: [\"lang\", \"cli\"]\n"));
+
+        let path = create(dir.path(), "rust.md", &content).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+        let err = create(dir.path(), "rust.md", "other").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn titles_with_quotes_stay_valid_frontmatter() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let title = r#"The "Best" \ Note"#;
+        let tags = ["lang".to_string()];
+        let vars = variables(&Config::default(), title, &tags, &now);
+        let content = template::render(template::DEFAULT_NOTE, &vars, &now, |_| None);
+        assert!(content.contains(r#"title: "The \"Best\" \\ Note""#));
+        let meta = crate::metadata::parse(&content);
+        assert_eq!(meta.title.as_deref(), Some(title));
+        assert_eq!(meta.tags, ["lang"]);
+    }
+
+    #[test]
+    fn tags_with_yaml_syntax_stay_valid_frontmatter() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let tags = ["a]b".to_string(), "k: v".to_string(), "x, y".to_string()];
+        let vars = variables(&Config::default(), "Rust: tips, tricks", &tags, &now);
+        let content = template::render(template::DEFAULT_NOTE, &vars, &now, |_| None);
+        assert!(content.contains(r#"tags: ["a]b", "k: v", "x, y"]"#));
+        let meta = crate::metadata::parse(&content);
+        assert_eq!(meta.title.as_deref(), Some("Rust: tips, tricks"));
+        assert_eq!(meta.tags, tags);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::config::Config;
+use crate::links::{self, Link, Resolver};
+use crate::tantivy_search;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever what is cached per note changes, older caches are rebuilt
+const CACHE_VERSION: u32 = 3;
+
+/// The link graph of a vault.
+///
+/// Built once from the parsed links of every note and cached beside the search
+/// index, later loads only re-read the notes whose mtime changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NotesMap {
+    #[serde(default)]
+    version: u32,
+    /// Whether links were resolved relative to the linking note's directory
+    nested: bool,
+    notes: BTreeMap<PathBuf, Note>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    /// Modification time when the links were parsed
+    mtime: u64,
+    /// Links in this note, in order
+    pub links: Vec<ResolvedLink>,
+    /// Files that link to this one
+    pub backlinks: Vec<PathBuf>,
+    /// Headings and block ids that links can point at
+    pub anchors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedLink {
+    pub link: Link,
+    /// The existing note the link points at, if any
+    pub target: Option<PathBuf>,
+    /// The existing file the link points at, if it isn't a note
+    pub attachment: Option<PathBuf>,
+    /// The anchor names no heading or block in the note it points at
+    pub broken_anchor: bool,
+}
+
+/// What a link points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStatus {
+    /// An existing note
+    Note,
+    /// A note that does not exist
+    Missing,
+    /// An existing file that isn't a note, like an image or a PDF
+    Attachment,
+    /// A URL
+    External,
+    /// A heading or block in the same note
+    Anchor,
+    /// A heading or block that does not exist, in an existing note
+    #[serde(rename = "missing-anchor")]
+    MissingAnchor,
+}
+
+impl LinkStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkStatus::Note => "note",
+            LinkStatus::Missing => "missing",
+            LinkStatus::Attachment => "attachment",
+            LinkStatus::External => "external",
+            LinkStatus::Anchor => "anchor",
+            LinkStatus::MissingAnchor => "missing-anchor",
+        }
+    }
+}
+
+impl ResolvedLink {
+    pub fn status(&self) -> LinkStatus {
+        if self.broken_anchor {
+            LinkStatus::MissingAnchor
+        } else if self.link.target.is_empty() {
+            LinkStatus::Anchor
+        } else if self.link.is_external() {
+            LinkStatus::External
+        } else if self.target.is_some() {
+            LinkStatus::Note
+        } else if self.attachment.is_some() {
+            LinkStatus::Attachment
+        } else {
+            LinkStatus::Missing
+        }
+    }
+}
+
+/// The canonical notes directory of the vault
+pub fn notes_dir(config: &Config) -> PathBuf {
+    Path::new(&config.note_taking_dir)
+        .canonicalize()
+        .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", config.note_taking_dir))
+}
+
+/// The notes directory of the vault and its graph, brought up to date
+pub fn load(config: &Config, nested: bool, verbose: bool) -> (PathBuf, NotesMap) {
+    let notes_dir = notes_dir(config);
+    let map = NotesMap::load(&notes_dir, &cache_file(config), nested, verbose);
+    (notes_dir, map)
+}
+
+/// The graph is kept beside the tantivy index
+pub fn cache_file(config: &Config) -> PathBuf {
+    Path::new(&tantivy_search::get_cache(config)).with_file_name("links.json")
+}
+
+impl NotesMap {
+    /// Load the cached graph and bring it up to date with `notes_dir`,
+    /// which should be canonical
+    pub fn load(notes_dir: &Path, cache_file: &Path, nested: bool, verbose: bool) -> NotesMap {
+        let mut cached: NotesMap = std::fs::read_to_string(cache_file)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .filter(|m: &NotesMap| m.version == CACHE_VERSION)
+            .unwrap_or_default();
+
+        let files = links::note_files(notes_dir);
+        let mut changed = cached.nested != nested || cached.notes.len() != files.len();
+        let mut map = NotesMap {
+            version: CACHE_VERSION,
+            nested,
+            notes: BTreeMap::new(),
+        };
+        for file in files {
+            let mtime = tantivy_search::mtime(&file).unwrap_or_default();
+            let note = match cached.notes.remove(&file) {
+                Some(note) if note.mtime == mtime => note,
+                _ => {
+                    changed = true;
+                    parse_note(&file, mtime, verbose)
+                }
+            };
+            map.notes.insert(file, note);
+        }
+
+        if changed {
+            map.set_backlinks(notes_dir);
+            if let Err(e) = map.save(cache_file) {
+                eprintln!("Unable to cache the link graph in {:?}: {e}", cache_file);
+            }
+        }
+        map
+    }
+
+    fn save(&self, cache_file: &Path) -> std::io::Result<()> {
+        if let Some(dir) = cache_file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let serialized = serde_json::to_string(self).expect("Unable to serialize link graph");
+        std::fs::write(cache_file, serialized)
+    }
+
+    /// Resolve every link and invert them into backlinks. Any note can change
+    /// what a wikilink resolves to, so this is redone for the whole vault.
+    fn set_backlinks(&mut self, notes_dir: &Path) {
+        let files: Vec<PathBuf> = self.notes.keys().cloned().collect();
+        let resolver = Resolver::new(notes_dir, &files, self.nested);
+
+        let anchors: BTreeMap<PathBuf, Vec<String>> = self
+            .notes
+            .iter()
+            .map(|(path, note)| (path.clone(), note.anchors.clone()))
+            .collect();
+
+        let mut edges = vec![];
+        for (path, note) in self.notes.iter_mut() {
+            note.backlinks.clear();
+            for resolved in note.links.iter_mut() {
+                resolved.target = resolver.resolve(path, &resolved.link);
+                resolved.attachment = match resolved.target {
+                    Some(_) => None,
+                    None => resolver.file(path, &resolved.link),
+                };
+                // Links within the same note point at its own anchors
+                let pointed_at = match resolved.link.target.is_empty() {
+                    true => Some(path),
+                    false => resolved.target.as_ref(),
+                };
+                resolved.broken_anchor = match (&resolved.link.anchor, pointed_at) {
+                    (Some(anchor), Some(target)) => anchors
+                        .get(target)
+                        .is_some_and(|a| !links::anchor_matches(anchor, a)),
+                    _ => false,
+                };
+                if let Some(target) = &resolved.target {
+                    edges.push((path.clone(), target.clone()));
+                }
+            }
+        }
+        for (from, to) in edges {
+            self.add_backlink(from, &to);
+        }
+    }
+
+    fn add_backlink(&mut self, from: PathBuf, to: &Path) {
+        if let Some(note) = self.notes.get_mut(to) {
+            if !note.backlinks.contains(&from) {
+                note.backlinks.push(from);
+            }
+        }
+    }
+
+    /// Every note, ordered by path
+    pub fn notes(&self) -> impl Iterator<Item = (&Path, &Note)> {
+        self.notes.iter().map(|(p, n)| (p.as_path(), n))
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Note> {
+        self.notes.get(path)
+    }
+
+    /// Each link pointing at `path`, with the note it is in
+    pub fn backlinks(&self, path: &Path) -> Vec<(&Path, &Link)> {
+        let Some(note) = self.get(path) else {
+            return vec![];
+        };
+        note.backlinks
+            .iter()
+            .filter_map(|source| Some((source, self.get(source)?)))
+            .flat_map(|(source, note)| {
+                note.links
+                    .iter()
+                    .filter(|l| l.target.as_deref() == Some(path))
+                    .map(move |l| (source.as_path(), &l.link))
+            })
+            .collect()
+    }
+
+    /// The links in `path`, resolved
+    pub fn forward_links(&self, path: &Path) -> &[ResolvedLink] {
+        self.get(path).map_or(&[], |n| n.links.as_slice())
+    }
+
+    /// The number of other notes `path` links to and the number linking to it
+    pub fn link_counts(&self, path: &Path) -> (usize, usize) {
+        self.get(path).map_or((0, 0), |note| {
+            let mut targets: Vec<&PathBuf> = note
+                .links
+                .iter()
+                .filter_map(|l| l.target.as_ref())
+                .filter(|t| *t != path && self.notes.contains_key(*t))
+                .collect();
+            targets.sort();
+            targets.dedup();
+            let sources = note.backlinks.iter().filter(|s| *s != path).count();
+            (targets.len(), sources)
+        })
+    }
+}
+
+fn parse_note(file: &Path, mtime: u64, verbose: bool) -> Note {
+    let content = std::fs::read_to_string(file).unwrap_or_else(|_| {
+        if verbose {
+            eprintln!("Failed to read {:?}", file);
+        }
+        String::new()
+    });
+    let org = file.extension().is_some_and(|e| e == "org");
+    Note {
+        mtime,
+        links: links::extract(&content, org)
+            .into_iter()
+            .map(|link| ResolvedLink {
+                link,
+                target: None,
+                attachment: None,
+                broken_anchor: false,
+            })
+            .collect(),
+        backlinks: vec![],
+        anchors: links::anchors(&content, org),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_is_cached_and_refreshed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_file = cache.path().join("links.json");
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("a.md"), "[b](b.md) [[c]] [b again](b)").unwrap();
+        std::fs::write(root.join("b.md"), "[a](a.md) [gone](gone.md)").unwrap();
+        std::fs::write(root.join("c.md"), "no links").unwrap();
+
+        let map = NotesMap::load(&root, &cache_file, false, false);
+        assert!(cache_file.exists());
+        assert_eq!(map.link_counts(&root.join("a.md")), (2, 1));
+        assert_eq!(map.link_counts(&root.join("c.md")), (0, 1));
+        assert_eq!(map.backlinks(&root.join("b.md")).len(), 2);
+        let statuses: Vec<LinkStatus> = map
+            .forward_links(&root.join("b.md"))
+            .iter()
+            .map(ResolvedLink::status)
+            .collect();
+        assert_eq!(statuses, vec![LinkStatus::Note, LinkStatus::Missing]);
+
+        // A new note linking to c is picked up on the next load
+        std::fs::write(root.join("d.md"), "[[c]]").unwrap();
+        let map = NotesMap::load(&root, &cache_file, false, false);
+        let sources: Vec<&Path> = map
+            .backlinks(&root.join("c.md"))
+            .into_iter()
+            .map(|(s, _)| s)
+            .collect();
+        assert_eq!(sources, vec![root.join("a.md"), root.join("d.md")]);
+    }
+
+    #[test]
+    fn broken_anchors_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("a.md"), "# Set Up\ntext ^b1\n").unwrap();
+        std::fs::write(
+            root.join("b.md"),
+            "# Own\n[a](a.md#set-up) [[a#Gone]] [[a#^b1]] [own](#own) [x](#nope) [a](a.md)",
+        )
+        .unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+        let statuses: Vec<LinkStatus> = map
+            .forward_links(&root.join("b.md"))
+            .iter()
+            .map(ResolvedLink::status)
+            .collect();
+        use LinkStatus::*;
+        assert_eq!(
+            statuses,
+            vec![Note, MissingAnchor, Note, Anchor, MissingAnchor, Note]
+        );
+    }
+}
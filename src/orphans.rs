@@ -0,0 +1,149 @@
+use crate::backlinks::display_path;
+use crate::config::Config;
+use crate::metadata;
+use crate::notes_map::{self, NotesMap};
+use crate::output::{self, Format, Record};
+use crate::tantivy_search;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which side of a note has no links
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unlinked {
+    /// No other note links to it
+    Orphan,
+    /// It links to no other note
+    DeadEnd,
+}
+
+/// Restricts which notes are reported
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Only notes under this directory, relative to the notes directory
+    pub dir: Option<PathBuf>,
+    /// Only notes last modified more than this many days ago
+    pub older_than: Option<u64>,
+    /// Only notes modified within this many days
+    pub newer_than: Option<u64>,
+}
+
+pub fn run(
+    config: Config,
+    unlinked: Unlinked,
+    filter: &Filter,
+    absolute: bool,
+    nested: bool,
+    verbose: bool,
+    format: Format,
+) {
+    let (notes_dir, map) = notes_map::load(&config, nested, verbose);
+
+    let dir = filter.dir.as_ref().map(|d| {
+        notes_dir
+            .join(d)
+            .canonicalize()
+            .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", d))
+    });
+    let now = SystemTime::now();
+
+    let mut records = vec![];
+    for note in find(&map, unlinked) {
+        if dir.as_ref().is_some_and(|d| !note.starts_with(d)) || !filter.age_matches(note, now) {
+            continue;
+        }
+        let shown = display_path(&notes_dir, note, absolute);
+        if format == Format::Plain {
+            println!("{shown}");
+        } else {
+            let mut record = Record::new(shown);
+            record.title = fs::read_to_string(note)
+                .ok()
+                .and_then(|c| metadata::parse(&c).title);
+            records.push(record);
+        }
+    }
+    output::print_json(&records, format);
+}
+
+/// Notes without incoming or outgoing links, ordered by path.
+/// Links from a note to itself don't count.
+fn find(map: &NotesMap, unlinked: Unlinked) -> Vec<&Path> {
+    map.notes()
+        .map(|(path, _)| path)
+        .filter(|path| {
+            let (outgoing, incoming) = map.link_counts(path);
+            match unlinked {
+                Unlinked::Orphan => incoming == 0,
+                Unlinked::DeadEnd => outgoing == 0,
+            }
+        })
+        .collect()
+}
+
+impl Filter {
+    fn age_matches(&self, note: &Path, now: SystemTime) -> bool {
+        if self.older_than.is_none() && self.newer_than.is_none() {
+            return true;
+        }
+        let Some(mtime) = tantivy_search::mtime(note) else {
+            return false;
+        };
+        let modified = UNIX_EPOCH + Duration::from_nanos(mtime);
+        let age = now.duration_since(modified).unwrap_or_default();
+        let days = |n: u64| Duration::from_secs(n * 24 * 60 * 60);
+        self.older_than.is_none_or(|n| age > days(n))
+            && self.newer_than.is_none_or(|n| age <= days(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backlinks::relpath;
+
+    #[test]
+    fn orphans_and_deadends_ignore_self_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("a.md"), "[b](b.md) [a](a.md)").unwrap();
+        std::fs::write(root.join("b.md"), "[missing](x.md) https://a.b").unwrap();
+        std::fs::write(root.join("c.md"), "[[c]] [[b]]").unwrap();
+        // Files outside the notes aren't outgoing links
+        std::fs::create_dir(root.join(".templates")).unwrap();
+        std::fs::write(root.join(".templates/t.md"), "").unwrap();
+        std::fs::write(root.join("paper.pdf"), "").unwrap();
+        std::fs::write(root.join("d.md"), "[p](paper.pdf) [t](.templates/t.md)").unwrap();
+
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+        let names = |unlinked| -> Vec<String> {
+            find(&map, unlinked)
+                .into_iter()
+                .map(|p| relpath(&root, p))
+                .collect()
+        };
+        assert_eq!(names(Unlinked::Orphan), vec!["a.md", "c.md", "d.md"]);
+        assert_eq!(names(Unlinked::DeadEnd), vec!["b.md", "d.md"]);
+    }
+
+    #[test]
+    fn age_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = dir.path().join("a.md");
+        std::fs::write(&note, "").unwrap();
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        let later = SystemTime::now() + week + Duration::from_secs(60);
+
+        let filter = |older_than, newer_than| Filter {
+            dir: None,
+            older_than,
+            newer_than,
+        };
+        assert!(filter(None, None).age_matches(&note, later));
+        assert!(filter(Some(7), None).age_matches(&note, later));
+        assert!(!filter(Some(8), None).age_matches(&note, later));
+        assert!(!filter(None, Some(7)).age_matches(&note, later));
+        assert!(filter(None, Some(7)).age_matches(&note, SystemTime::now()));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::backlinks::display_path;
+use crate::config::Config;
+use crate::links::LinkKind;
+use crate::metadata;
+use crate::notes_map::{self, LinkStatus, NotesMap, ResolvedLink};
+use crate::output::{self, Format, Record};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A `Record` for a link, `path` is where the link points
+#[derive(Debug, Serialize)]
+struct LinkRecord {
+    #[serde(flatten)]
+    record: Record,
+    status: LinkStatus,
+    kind: LinkKind,
+    anchor: Option<String>,
+}
+
+pub fn run(
+    config: Config,
+    file: &PathBuf,
+    absolute: bool,
+    nested: bool,
+    verbose: bool,
+    format: Format,
+) {
+    // get the absolute path of the file
+    let source = file
+        .canonicalize()
+        .unwrap_or_else(|_| panic!("Unable to get abs path of {:?}", file));
+
+    let (notes_dir, map) = notes_map::load(&config, nested, verbose);
+
+    let records = records(&map, &source, &notes_dir, absolute);
+    if format == Format::Plain {
+        for record in &records {
+            println!("{}", plain(record));
+        }
+    }
+    output::print_json(&records, format);
+}
+
+/// A record per link in `source`, in order
+fn records(map: &NotesMap, source: &Path, notes_dir: &Path, absolute: bool) -> Vec<LinkRecord> {
+    let content = fs::read_to_string(source).unwrap_or_default();
+    map.forward_links(source)
+        .iter()
+        .map(|resolved| {
+            let link = &resolved.link;
+            let mut record = Record::new(target_display(resolved, notes_dir, absolute));
+            record.title = resolved
+                .target
+                .as_ref()
+                .and_then(|t| fs::read_to_string(t).ok())
+                .and_then(|c| metadata::parse(&c).title);
+            record.snippet = content.lines().nth(link.line - 1).map(str::to_string);
+            record.line = Some(link.line);
+            LinkRecord {
+                record,
+                status: resolved.status(),
+                kind: link.kind,
+                anchor: link.anchor.clone(),
+            }
+        })
+        .collect()
+}
+
+/// `line:status target#anchor`
+fn plain(record: &LinkRecord) -> String {
+    let anchor = record
+        .anchor
+        .as_ref()
+        .map(|a| format!("#{a}"))
+        .unwrap_or_default();
+    format!(
+        "{}:{:<8} {}{anchor}",
+        record.record.line.unwrap_or_default(),
+        record.status.as_str(),
+        record.record.path
+    )
+}
+
+/// Resolved notes and attachments follow `--absolute`, everything else is
+/// shown as written
+fn target_display(resolved: &ResolvedLink, notes_dir: &Path, absolute: bool) -> String {
+    match resolved.target.as_ref().or(resolved.attachment.as_ref()) {
+        Some(target) => display_path(notes_dir, target, absolute),
+        None => resolved.link.target.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_shown_with_their_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/b.md"), "---\ntitle: Bee\n---\n# Set Up").unwrap();
+        fs::write(root.join("paper.pdf"), "").unwrap();
+        fs::write(
+            root.join("a.md"),
+            "# Own\n[b](sub/b.md#set-up) [[b#Gone]] [u](https://a.b/c)\n[o](#own) [x](x.md) [p](paper.pdf)",
+        )
+        .unwrap();
+        let map = NotesMap::load(&root, &cache.path().join("links.json"), false, false);
+
+        let source = root.join("a.md");
+        let shown: Vec<String> = records(&map, &source, &root, false)
+            .iter()
+            .map(plain)
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                "2:note     sub/b.md#set-up",
+                "2:missing-anchor sub/b.md#Gone",
+                "2:external https://a.b/c",
+                "3:anchor   #own",
+                "3:missing  x.md",
+                "3:attachment paper.pdf",
+            ]
+        );
+
+        let records = records(&map, &source, &root, true);
+        let json = serde_json::to_value(&records[0]).unwrap();
+        assert_eq!(json["path"], root.join("sub/b.md").display().to_string());
+        assert_eq!(json["title"], "Bee");
+        assert_eq!(json["kind"], "inline");
+        assert_eq!(json["anchor"], "set-up");
+        assert_eq!(json["line"], 2);
+        // Only resolved notes become absolute
+        assert_eq!(
+            serde_json::to_value(&records[2]).unwrap()["path"],
+            "https://a.b/c"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// How results are written to stdout, diagnostics always go to stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Human readable text
+    #[default]
+    Plain,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Graphviz, only for `graph export`
+    Dot,
+    /// GraphML, only for `graph export`
+    Graphml,
+}
+
+impl Format {
+    /// Formats that only apply to a whole graph rather than to records
+    pub fn is_graph(&self) -> bool {
+        matches!(self, Format::Dot | Format::Graphml)
+    }
+}
+
+/// A result of search, backlinks, find etc.
+///
+/// Every key is always present so that consumers can rely on the shape,
+/// fields that don't apply to a command are `null`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Record {
+    pub path: String,
+    pub title: Option<String>,
+    pub score: Option<f32>,
+    pub snippet: Option<String>,
+    /// 1-based line number of the snippet
+    pub line: Option<usize>,
+}
+
+impl Record {
+    pub fn new(path: String) -> Record {
+        Record {
+            path,
+            ..Record::default()
+        }
+    }
+}
+
+/// Print records as JSON, `Format::Plain` is left to the caller
+/// as every command has its own text layout
+pub fn print_json<T: Serialize>(records: &[T], format: Format) {
+    match format {
+        Format::Plain | Format::Dot | Format::Graphml => {}
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(records).expect("Unable to serialize records")
+        ),
+        Format::Jsonl => {
+            for record in records {
+                println!(
+                    "{}",
+                    serde_json::to_string(record).expect("Unable to serialize record")
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_keep_null_fields() {
+        let record = Record::new("a.md".to_string());
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"path":"a.md","title":null,"score":null,"snippet":null,"line":null}"#
+        );
+    }
+}
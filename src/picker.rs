@@ -0,0 +1,469 @@
+use crate::find;
+use crate::links;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::crossterm::{cursor, execute};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Lines scrolled by page up and page down in the preview
+const PAGE: u16 = 10;
+
+/// The items for a query, as paths relative to the picker's root or absolute
+type Source<'a> = Box<dyn FnMut(&str) -> Vec<String> + 'a>;
+
+/// An interactive list of notes, filtered as the query is typed, with a
+/// preview of the note under the cursor
+pub struct Picker<'a> {
+    source: Source<'a>,
+    root: PathBuf,
+    /// Whether tab selects several items
+    multi: bool,
+    query: String,
+    items: Vec<String>,
+    cursor: usize,
+    /// In the order they were selected
+    selected: Vec<String>,
+    list: ListState,
+    scroll: u16,
+    /// The highlighted note under the cursor, so it is only read once
+    preview: Option<(String, Text<'static>)>,
+}
+
+/// How the picker was closed
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Accept(Vec<String>),
+    Cancel,
+}
+
+impl<'a> Picker<'a> {
+    pub fn new(
+        root: &Path,
+        source: impl FnMut(&str) -> Vec<String> + 'a,
+        multi: bool,
+    ) -> Picker<'a> {
+        let mut picker = Picker {
+            source: Box::new(source),
+            root: root.to_path_buf(),
+            multi,
+            query: String::new(),
+            items: vec![],
+            cursor: 0,
+            selected: vec![],
+            list: ListState::default(),
+            scroll: 0,
+            preview: None,
+        };
+        picker.refresh();
+        picker
+    }
+
+    /// Ask the source for the items matching the query
+    fn refresh(&mut self) {
+        self.items = (self.source)(&self.query);
+        self.cursor = 0;
+        self.scroll = 0;
+    }
+
+    fn move_cursor(&mut self, down: bool) {
+        self.cursor = match down {
+            true => (self.cursor + 1).min(self.items.len().saturating_sub(1)),
+            false => self.cursor.saturating_sub(1),
+        };
+        self.scroll = 0;
+    }
+
+    /// Select or deselect the item under the cursor
+    fn toggle(&mut self) {
+        let Some(item) = self.items.get(self.cursor) else {
+            return;
+        };
+        match self.selected.iter().position(|s| s == item) {
+            Some(i) => {
+                self.selected.remove(i);
+            }
+            None => self.selected.push(item.clone()),
+        }
+    }
+
+    /// Update the picker for a key press, returns how it was closed if it was
+    fn handle(&mut self, event: Event) -> Option<Outcome> {
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind,
+            ..
+        }) = event
+        else {
+            return None;
+        };
+        if kind == KeyEventKind::Release {
+            return None;
+        }
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        match (code, ctrl) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c' | 'g'), true) => return Some(Outcome::Cancel),
+            (KeyCode::Enter, _) => {
+                let current = self.items.get(self.cursor).cloned();
+                return Some(Outcome::Accept(match self.selected.is_empty() {
+                    true => current.into_iter().collect(),
+                    false => self.selected.clone(),
+                }));
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('p' | 'k'), true) => self.move_cursor(false),
+            (KeyCode::Down, _) | (KeyCode::Char('n' | 'j'), true) => self.move_cursor(true),
+            (KeyCode::Tab, _) if self.multi => {
+                self.toggle();
+                self.move_cursor(true);
+            }
+            (KeyCode::BackTab, _) if self.multi => {
+                self.toggle();
+                self.move_cursor(false);
+            }
+            (KeyCode::PageDown, _) => self.scroll = self.scroll.saturating_add(PAGE),
+            (KeyCode::PageUp, _) => self.scroll = self.scroll.saturating_sub(PAGE),
+            (KeyCode::Backspace, _) => {
+                self.query.pop();
+                self.refresh();
+            }
+            (KeyCode::Char('u'), true) => {
+                self.query.clear();
+                self.refresh();
+            }
+            (KeyCode::Char(c), false) => {
+                self.query.push(c);
+                self.refresh();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// The list and prompt on the left, the preview on the right
+    fn draw(&mut self, frame: &mut Frame) {
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(frame.area());
+        let [list_area, prompt_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(left);
+
+        let items: Vec<ListItem> = self
+            .items
+            .iter()
+            .map(|item| {
+                let mark = if self.selected.contains(item) {
+                    "* "
+                } else {
+                    "  "
+                };
+                ListItem::new(format!("{mark}{item}"))
+            })
+            .collect();
+        let list = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        self.list
+            .select((!self.items.is_empty()).then_some(self.cursor));
+        frame.render_stateful_widget(list, list_area, &mut self.list);
+
+        let mut count = format!("  {}", self.items.len());
+        if !self.selected.is_empty() {
+            count.push_str(&format!(" ({} selected)", self.selected.len()));
+        }
+        let prompt = Line::from(vec![
+            Span::styled("> ", Style::new().fg(Color::Blue)),
+            Span::raw(self.query.clone()),
+            Span::styled(count, Style::new().fg(Color::DarkGray)),
+        ]);
+        frame.render_widget(prompt, prompt_area);
+        let x = prompt_area.x + 2 + self.query.chars().count() as u16;
+        frame.set_cursor_position((x.min(prompt_area.right().saturating_sub(1)), prompt_area.y));
+
+        let Some(item) = self.items.get(self.cursor).cloned() else {
+            frame.render_widget(Block::bordered(), right);
+            return;
+        };
+        if self
+            .preview
+            .as_ref()
+            .is_none_or(|(shown, _)| *shown != item)
+        {
+            let path = self.root.join(&item);
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            let org = path.extension().is_some_and(|e| e == "org");
+            self.preview = Some((item.clone(), highlight(&content, org)));
+        }
+        let text = self
+            .preview
+            .as_ref()
+            .map(|(_, t)| t.clone())
+            .unwrap_or_default();
+        let preview = Paragraph::new(text)
+            .block(Block::bordered().title(item))
+            .scroll((self.scroll, 0));
+        frame.render_widget(preview, right);
+    }
+}
+
+/// Run the picker on `terminal` with `events` until an item is accepted,
+/// `None` if it was cancelled or the events ran out
+fn run<B: Backend>(
+    terminal: &mut Terminal<B>,
+    picker: &mut Picker,
+    events: impl IntoIterator<Item = Event>,
+) -> io::Result<Option<Vec<String>>> {
+    terminal.draw(|frame| picker.draw(frame))?;
+    for event in events {
+        match picker.handle(event) {
+            Some(Outcome::Accept(items)) => return Ok(Some(items)),
+            Some(Outcome::Cancel) => return Ok(None),
+            None => {}
+        }
+        terminal.draw(|frame| picker.draw(frame))?;
+    }
+    Ok(None)
+}
+
+/// Raw mode and the alternate screen, left again when dropped
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        terminal::enable_raw_mode()
+            .map_err(|e| io::Error::new(e.kind(), format!("No terminal to pick a note in: {e}")))?;
+        let screen = Screen;
+        execute!(io::stderr(), EnterAlternateScreen)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Pick notes from `source` in the terminal. The picker is drawn on stderr so
+/// that the picked paths can be piped from stdout.
+pub fn pick(
+    root: &Path,
+    source: impl FnMut(&str) -> Vec<String>,
+    multi: bool,
+) -> io::Result<Option<Vec<PathBuf>>> {
+    let mut picker = Picker::new(root, source, multi);
+    let _screen = Screen::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+    let events = std::iter::from_fn(|| event::read().ok());
+    let picked = run(&mut terminal, &mut picker, events)?;
+    Ok(picked.map(|items| items.iter().map(|i| root.join(i)).collect()))
+}
+
+/// The notes under `root` fuzzy matched by their path, the best match first
+pub fn files(root: &Path) -> impl FnMut(&str) -> Vec<String> {
+    let files: Vec<String> = links::note_files(root)
+        .iter()
+        .map(|p| p.strip_prefix(root).unwrap_or(p).display().to_string())
+        .collect();
+    move |query| {
+        if query.trim().is_empty() {
+            return files.clone();
+        }
+        let mut scored: Vec<(f32, &String)> = files
+            .iter()
+            .filter_map(|f| Some((find::score(query, f)?, f)))
+            .collect();
+        // Stable, so equal scores stay in path order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().map(|(_, f)| f.clone()).collect()
+    }
+}
+
+// Spans styled within a line: code, links, URLs, bold and tags
+static INLINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?P<code>`[^`]+`)|(?P<link>\[\[[^\]]+\]\](?:\[[^\]]*\]\])?|\[[^\]]*\]\([^)]*\))|(?P<url>https?://[^\s)>\]]+)|(?P<bold>\*\*[^*]+\*\*)|(?P<tag>(?:^|\s)#[\w/-]+)",
+    )
+    .expect("Failed to create regex")
+});
+
+/// Colour a markdown or org note for the preview
+fn highlight(content: &str, org: bool) -> Text<'static> {
+    let mut lines = vec![];
+    let mut frontmatter = content.starts_with("---");
+    let mut in_fence = false;
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let lower = trimmed.to_lowercase();
+        let fence = match org {
+            true => lower.starts_with("#+begin_src") || lower.starts_with("#+end_src"),
+            false => trimmed.starts_with("```") || trimmed.starts_with("~~~"),
+        };
+        let heading = match org {
+            true => line.len() - line.trim_start_matches('*').len(),
+            false => line.len() - line.trim_start_matches('#').len(),
+        };
+        let is_heading = heading > 0 && line[heading..].starts_with(' ');
+
+        let style = |color| Style::new().fg(color);
+        let styled = if frontmatter {
+            if i > 0 && line.trim_end() == "---" {
+                frontmatter = false;
+            }
+            Line::styled(line.to_string(), style(Color::DarkGray))
+        } else if fence || in_fence {
+            in_fence ^= fence;
+            Line::styled(line.to_string(), style(Color::Green))
+        } else if is_heading {
+            let colors = [Color::Magenta, Color::Blue, Color::Cyan];
+            let color = colors[(heading - 1).min(colors.len() - 1)];
+            Line::styled(line.to_string(), style(color).add_modifier(Modifier::BOLD))
+        } else if trimmed.starts_with('>') || (org && lower.starts_with("#+")) {
+            Line::styled(
+                line.to_string(),
+                style(Color::DarkGray).add_modifier(Modifier::ITALIC),
+            )
+        } else {
+            Line::from(inline(line))
+        };
+        lines.push(styled);
+    }
+    Text::from(lines)
+}
+
+/// The spans of a line of prose, with list markers and inline markup styled
+fn inline(line: &str) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let indent = line.len() - line.trim_start().len();
+    let marker = line[indent..].split_once(' ').map(|(m, _)| m).filter(|m| {
+        matches!(*m, "-" | "*" | "+")
+            || m.strip_suffix('.')
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+    });
+    let mut start = 0;
+    if let Some(marker) = marker {
+        start = indent + marker.len();
+        spans.push(Span::styled(
+            line[..start].to_string(),
+            Style::new().fg(Color::Yellow),
+        ));
+    }
+    let rest = &line[start..];
+    let mut last = 0;
+    for caps in INLINE.captures_iter(rest) {
+        let (name, m) = ["code", "link", "url", "bold", "tag"]
+            .into_iter()
+            .find_map(|n| Some((n, caps.name(n)?)))
+            .expect("A group matched");
+        let style = match name {
+            "code" => Style::new().fg(Color::Green),
+            "link" | "url" => Style::new()
+                .fg(Color::Blue)
+                .add_modifier(Modifier::UNDERLINED),
+            "bold" => Style::new().add_modifier(Modifier::BOLD),
+            _ => Style::new().fg(Color::Yellow),
+        };
+        spans.push(Span::raw(rest[last..m.start()].to_string()));
+        spans.push(Span::styled(m.as_str().to_string(), style));
+        last = m.end();
+    }
+    spans.push(Span::raw(rest[last..].to_string()));
+    spans.retain(|s| !s.content.is_empty());
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn keys(typed: &str) -> Vec<Event> {
+        typed
+            .chars()
+            .map(|c| Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)))
+            .collect()
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        buffer.content().iter().map(|c| c.symbol()).collect()
+    }
+
+    #[test]
+    fn scripted_keys_filter_and_select() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("rust.md"), "# Rust Notes\n`cargo`").unwrap();
+        fs::write(root.join("rusty-nail.md"), "").unwrap();
+        fs::write(root.join("python.md"), "").unwrap();
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+
+        let mut picker = Picker::new(root, files(root), true);
+        let picked = run(&mut terminal, &mut picker, keys("rus")).unwrap();
+        assert_eq!(picked, None);
+        assert_eq!(picker.items, ["rust.md", "rusty-nail.md"]);
+        assert!(screen(&terminal).contains("# Rust Notes"));
+
+        let script = [
+            key(KeyCode::Down),
+            key(KeyCode::Tab),
+            key(KeyCode::Up),
+            key(KeyCode::Tab),
+            key(KeyCode::Enter),
+        ];
+        let picked = run(&mut terminal, &mut picker, script).unwrap();
+        assert_eq!(
+            picked,
+            Some(vec!["rusty-nail.md".to_string(), "rust.md".to_string()])
+        );
+
+        let mut picker = Picker::new(root, files(root), false);
+        let script = keys("py")
+            .into_iter()
+            .chain([key(KeyCode::Tab), key(KeyCode::Enter)]);
+        let picked = run(&mut terminal, &mut picker, script).unwrap();
+        assert_eq!(picked, Some(vec!["python.md".to_string()]));
+
+        let script = keys("zzz").into_iter().chain([key(KeyCode::Esc)]);
+        let mut picker = Picker::new(root, files(root), false);
+        assert_eq!(run(&mut terminal, &mut picker, script).unwrap(), None);
+    }
+
+    #[test]
+    fn markdown_is_highlighted() {
+        let text = highlight(
+            "---\ntitle: a\n---\n## Setup\n- run `cargo` #rust\n```\n# not a heading\n```",
+            false,
+        );
+        let style = |line: usize| text.lines[line].style;
+        assert_eq!(style(1).fg, Some(Color::DarkGray));
+        assert_eq!(style(3).fg, Some(Color::Blue));
+        assert_eq!(style(6).fg, Some(Color::Green));
+        let spans: Vec<(&str, Option<Color>)> = text.lines[4]
+            .spans
+            .iter()
+            .map(|s| (s.content.as_ref(), s.style.fg))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("-", Some(Color::Yellow)),
+                (" run ", None),
+                ("`cargo`", Some(Color::Green)),
+                (" #rust", Some(Color::Yellow)),
+            ]
+        );
+    }
+}
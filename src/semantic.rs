@@ -0,0 +1,549 @@
+use crate::config::Config;
+use crate::metadata;
+use crate::tantivy_search::{self, Manifest, ManifestEntry, MatchLine, SearchHit, SearchOptions};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Turns text into a fixed size vector.
+///
+/// Implement this to plug in another model, e.g. a local sentence transformer.
+/// Vectors should be L2 normalized so that the dot product is the cosine similarity.
+pub trait Embedder {
+    /// Stored beside the vectors, a different backend forces a rebuild
+    fn name(&self) -> String;
+    fn dim(&self) -> usize;
+    /// Learn corpus statistics before embedding, a no-op for pretrained models
+    fn fit(&mut self, _texts: &[&str]) {}
+    /// State learnt by `fit` that has to be saved with the vectors
+    fn state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+    fn restore(&mut self, _state: &serde_json::Value) {}
+    fn embed(&self, text: &str) -> Vec<f32>;
+}
+
+/// Same size as the common small sentence transformers so that one can be swapped in
+const DIM: usize = 384;
+
+/// Sections longer than this are split at paragraph boundaries
+const MAX_CHUNK_CHARS: usize = 1000;
+
+/// A CPU only, deterministic embedding: TF-IDF weights of hashed tokens.
+///
+/// It only captures shared vocabulary rather than meaning, but needs no model
+/// files and gives the same vectors on every machine, which keeps tests stable.
+pub struct HashedTfIdf {
+    dim: usize,
+    idf: Vec<f32>,
+}
+
+impl HashedTfIdf {
+    pub fn new(dim: usize) -> HashedTfIdf {
+        HashedTfIdf {
+            dim,
+            idf: vec![1.0; dim],
+        }
+    }
+
+    fn bucket(&self, token: &str) -> usize {
+        (fnv1a(token.as_bytes()) % self.dim as u64) as usize
+    }
+}
+
+impl Embedder for HashedTfIdf {
+    fn name(&self) -> String {
+        format!("hashed-tfidf-{}", self.dim)
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn fit(&mut self, texts: &[&str]) {
+        let mut df = vec![0usize; self.dim];
+        for text in texts {
+            let mut buckets: Vec<usize> = tokens(text).map(|t| self.bucket(&t)).collect();
+            buckets.sort_unstable();
+            buckets.dedup();
+            for b in buckets {
+                df[b] += 1;
+            }
+        }
+        let n = texts.len() as f32;
+        self.idf = df
+            .iter()
+            .map(|&d| ((1.0 + n) / (1.0 + d as f32)).ln() + 1.0)
+            .collect();
+    }
+
+    fn state(&self) -> serde_json::Value {
+        serde_json::json!(self.idf)
+    }
+
+    fn restore(&mut self, state: &serde_json::Value) {
+        if let Ok(idf) = serde_json::from_value::<Vec<f32>>(state.clone()) {
+            if idf.len() == self.dim {
+                self.idf = idf;
+            }
+        }
+    }
+
+    fn embed(&self, text: &str) -> Vec<f32> {
+        let mut tf: HashMap<usize, f32> = HashMap::new();
+        for token in tokens(text) {
+            *tf.entry(self.bucket(&token)).or_default() += 1.0;
+        }
+        let mut vector = vec![0.0; self.dim];
+        for (b, count) in tf {
+            // Sublinear tf so that one repeated word doesn't dominate
+            vector[b] = (1.0 + count.ln()) * self.idf[b];
+        }
+        normalize(&mut vector);
+        vector
+    }
+}
+
+/// Lowercase alphanumeric words, single characters are noise
+fn tokens(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() > 1)
+        .map(str::to_lowercase)
+}
+
+/// Stable across platforms and Rust versions, unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// A section of a note, the unit that is embedded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Chunk {
+    path: String,
+    title: Option<String>,
+    /// 1-based line the chunk starts on
+    line: usize,
+    /// First line of the chunk, shown in results
+    snippet: String,
+}
+
+/// Chunk metadata and embedder state, the vectors themselves are in `vectors.bin`
+#[derive(Debug, Serialize, Deserialize)]
+struct StoreMeta {
+    backend: String,
+    dim: usize,
+    state: serde_json::Value,
+    chunks: Vec<Chunk>,
+}
+
+struct VectorStore {
+    chunks: Vec<Chunk>,
+    /// Row major, one row of `dim` per chunk
+    vectors: Vec<f32>,
+    dim: usize,
+    /// The mtime and hash of each embedded note, as for the search index
+    manifest: Manifest,
+}
+
+impl VectorStore {
+    /// Chunk and embed every note
+    fn build(notes_dir: &Path, embedder: &mut dyn Embedder, verbose: bool) -> VectorStore {
+        let mut chunks = vec![];
+        let mut texts = vec![];
+        let mut manifest = Manifest::default();
+        for file in tantivy_search::note_paths(notes_dir) {
+            let Some((entry, content)) = read_note(&file, verbose) else {
+                continue;
+            };
+            for (chunk, text) in note_chunks(&file, &content) {
+                chunks.push(chunk);
+                texts.push(text);
+            }
+            manifest.files.insert(file.display().to_string(), entry);
+        }
+
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        embedder.fit(&texts);
+        let vectors = texts.iter().flat_map(|t| embedder.embed(t)).collect();
+        eprintln!("Embedded {} chunks", chunks.len());
+        VectorStore {
+            chunks,
+            vectors,
+            dim: embedder.dim(),
+            manifest,
+        }
+    }
+
+    /// Re-embed the notes that changed since the store was saved and drop the
+    /// deleted ones. Returns whether anything changed.
+    ///
+    /// The embedder keeps what it learnt from the notes at the last build, only
+    /// a full rebuild fits it again.
+    fn update(&mut self, notes_dir: &Path, embedder: &dyn Embedder, verbose: bool) -> bool {
+        let mut manifest = Manifest::default();
+        let mut changed = vec![];
+        let mut refreshed = false;
+        for file in tantivy_search::note_paths(notes_dir) {
+            let key = file.display().to_string();
+            let Some(mtime) = tantivy_search::mtime(&file) else {
+                continue;
+            };
+            let old = self.manifest.files.remove(&key);
+            if let Some(old) = old.clone().filter(|e| e.mtime == mtime) {
+                manifest.files.insert(key, old);
+                continue;
+            }
+            let Some((entry, content)) = read_note(&file, verbose) else {
+                continue;
+            };
+            refreshed = true;
+            if old.is_none_or(|old| old.hash != entry.hash) {
+                changed.push((file, content));
+            }
+            manifest.files.insert(key, entry);
+        }
+        // What is left in the old manifest was deleted
+        let mut stale: Vec<String> = self.manifest.files.drain().map(|(k, _)| k).collect();
+        stale.extend(changed.iter().map(|(file, _)| file.display().to_string()));
+        self.manifest = manifest;
+        if stale.is_empty() {
+            return refreshed;
+        }
+
+        let (mut chunks, mut vectors) = (vec![], vec![]);
+        for (chunk, vector) in self
+            .chunks
+            .drain(..)
+            .zip(self.vectors.chunks_exact(self.dim))
+        {
+            if !stale.contains(&chunk.path) {
+                chunks.push(chunk);
+                vectors.extend_from_slice(vector);
+            }
+        }
+        for (file, content) in &changed {
+            for (chunk, text) in note_chunks(file, content) {
+                chunks.push(chunk);
+                vectors.extend(embedder.embed(&text));
+            }
+        }
+        eprintln!(
+            "Embedded {} changed notes, dropped {} deleted ones",
+            changed.len(),
+            stale.len() - changed.len()
+        );
+        self.chunks = chunks;
+        self.vectors = vectors;
+        true
+    }
+
+    fn save(&self, dir: &Path, embedder: &dyn Embedder) -> io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let meta = StoreMeta {
+            backend: embedder.name(),
+            dim: self.dim,
+            state: embedder.state(),
+            chunks: self.chunks.clone(),
+        };
+        let serialized = serde_json::to_string(&meta).expect("Unable to serialize vector store");
+        std::fs::write(dir.join("meta.json"), serialized)?;
+        let bytes: Vec<u8> = self.vectors.iter().flat_map(|x| x.to_le_bytes()).collect();
+        std::fs::write(dir.join("vectors.bin"), bytes)?;
+        self.manifest.save(dir)
+    }
+
+    /// `None` if there is no store, it was built by another backend or
+    /// without a manifest
+    fn load(dir: &Path, embedder: &mut dyn Embedder) -> Option<VectorStore> {
+        let meta = std::fs::read_to_string(dir.join("meta.json")).ok()?;
+        let meta: StoreMeta = serde_json::from_str(&meta).ok()?;
+        if meta.backend != embedder.name() || meta.dim != embedder.dim() {
+            return None;
+        }
+        let bytes = std::fs::read(dir.join("vectors.bin")).ok()?;
+        let vectors: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if vectors.len() != meta.chunks.len() * meta.dim {
+            return None;
+        }
+        let manifest = Manifest::load(dir)?;
+        embedder.restore(&meta.state);
+        Some(VectorStore {
+            chunks: meta.chunks,
+            vectors,
+            dim: meta.dim,
+            manifest,
+        })
+    }
+
+    /// The `n` notes whose best chunk is nearest to the query
+    fn search(&self, embedder: &dyn Embedder, query: &str, n: usize) -> Vec<SearchHit> {
+        let query = embedder.embed(query);
+        let mut best: HashMap<&str, (f32, &Chunk)> = HashMap::new();
+        for (chunk, vector) in self.chunks.iter().zip(self.vectors.chunks_exact(self.dim)) {
+            let score: f32 = query.iter().zip(vector).map(|(a, b)| a * b).sum();
+            if score <= 0.0 {
+                continue;
+            }
+            let entry = best.entry(chunk.path.as_str()).or_insert((score, chunk));
+            if score > entry.0 {
+                *entry = (score, chunk);
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = best
+            .into_values()
+            .map(|(score, chunk)| SearchHit {
+                path: chunk.path.clone(),
+                title: chunk.title.clone(),
+                score,
+                lines: vec![MatchLine {
+                    number: chunk.line,
+                    text: chunk.snippet.clone(),
+                    highlights: vec![],
+                }],
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.path.cmp(&b.path)));
+        hits.truncate(n);
+        hits
+    }
+}
+
+/// Split a note into sections at headings, long sections are split further at
+/// blank lines. Returns the 1-based start line and text of each chunk.
+fn chunk(content: &str) -> Vec<(usize, String)> {
+    let (_, body) = metadata::split_frontmatter(content);
+    let offset = content[..content.len() - body.len()].lines().count();
+
+    let mut chunks = vec![];
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in body.lines().enumerate() {
+        let number = offset + i + 1;
+        let is_heading = line.starts_with('#') && line.trim_start_matches('#').starts_with(' ');
+        let too_long = line.trim().is_empty()
+            && current
+                .as_ref()
+                .is_some_and(|(_, text)| text.len() > MAX_CHUNK_CHARS);
+        if is_heading || too_long {
+            chunks.extend(current.take());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (_, text) = current.get_or_insert_with(|| (number, String::new()));
+        text.push_str(line);
+        text.push('\n');
+    }
+    chunks.extend(current);
+    chunks
+}
+
+/// The content of `file` and its manifest entry
+fn read_note(file: &Path, verbose: bool) -> Option<(ManifestEntry, String)> {
+    let mtime = tantivy_search::mtime(file)?;
+    let Ok(content) = std::fs::read_to_string(file) else {
+        if verbose {
+            eprintln!("Failed to read {}", file.display());
+        }
+        return None;
+    };
+    let hash = tantivy_search::hash(&content);
+    Some((ManifestEntry { mtime, hash }, content))
+}
+
+/// The chunks of the note at `file` with the text to embed for each
+fn note_chunks(file: &Path, content: &str) -> Vec<(Chunk, String)> {
+    let path = file.display().to_string();
+    let title = metadata::parse(content).title;
+    chunk(content)
+        .into_iter()
+        .map(|(line, text)| {
+            let chunk = Chunk {
+                path: path.clone(),
+                title: title.clone(),
+                line,
+                snippet: text.lines().next().unwrap_or_default().to_string(),
+            };
+            (chunk, text)
+        })
+        .collect()
+}
+
+/// The vectors are kept beside the tantivy index
+fn store_dir(config: &Config) -> PathBuf {
+    Path::new(&tantivy_search::get_cache(config)).with_file_name("semantic")
+}
+
+pub fn run(config: Config, verbose: bool, query: &str, opts: &SearchOptions) -> io::Result<()> {
+    let hits = search(
+        &config,
+        verbose,
+        opts.reindex || opts.init,
+        query,
+        opts.count,
+    )?;
+    tantivy_search::print_results(&hits, opts.paths_only, opts.format);
+    Ok(())
+}
+
+/// The `n` nearest notes. The vectors are rebuilt if `rebuild` or missing,
+/// else brought up to date with the notes.
+pub fn search(
+    config: &Config,
+    verbose: bool,
+    rebuild: bool,
+    query: &str,
+    n: usize,
+) -> io::Result<Vec<SearchHit>> {
+    let dir = store_dir(config);
+    let notes_dir = Path::new(&config.note_taking_dir);
+    let mut embedder = HashedTfIdf::new(DIM);
+
+    let store = match (rebuild, VectorStore::load(&dir, &mut embedder)) {
+        (false, Some(mut store)) => {
+            if store.update(notes_dir, &embedder, verbose) {
+                store.save(&dir, &embedder)?;
+            }
+            store
+        }
+        _ => {
+            let store = VectorStore::build(notes_dir, &mut embedder, verbose);
+            store.save(&dir, &embedder)?;
+            store
+        }
+    };
+
+    Ok(store.search(&embedder, query, n))
+}
+
+/// Update the vectors after notes were changed by nt itself. Missing vectors
+/// are left for the next semantic search to build.
+pub fn update_store(config: &Config, verbose: bool) -> io::Result<()> {
+    let dir = store_dir(config);
+    let mut embedder = HashedTfIdf::new(DIM);
+    let Some(mut store) = VectorStore::load(&dir, &mut embedder) else {
+        return Ok(());
+    };
+    if store.update(Path::new(&config.note_taking_dir), &embedder, verbose) {
+        store.save(&dir, &embedder)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_split_at_headings_and_skip_frontmatter() {
+        let note = "---\ntitle: T\n---\n# One\nfirst\n\nstill one\n## Two\nsecond\n";
+        assert_eq!(
+            chunk(note),
+            vec![
+                (4, "# One\nfirst\nstill one\n".to_string()),
+                (8, "## Two\nsecond\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn hashed_embeddings_are_deterministic_and_normalized() {
+        let embedder = HashedTfIdf::new(DIM);
+        let a = embedder.embed("The borrow checker");
+        assert_eq!(a, embedder.embed("the BORROW checker"));
+        let norm: f32 = a.iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert!(embedder.embed("!").iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn nearest_neighbours_round_trip_through_disk() {
+        let notes = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(
+            notes.path().join("rust.md"),
+            "# Rust\nownership and the borrow checker\n\n# Cargo\ncrates and workspaces\n",
+        )
+        .unwrap();
+        std::fs::write(
+            notes.path().join("garden.md"),
+            "# Garden\ntomatoes need sun and water\n",
+        )
+        .unwrap();
+
+        let mut embedder = HashedTfIdf::new(DIM);
+        let store = VectorStore::build(notes.path(), &mut embedder, false);
+        store.save(cache.path(), &embedder).unwrap();
+
+        let mut embedder = HashedTfIdf::new(DIM);
+        let store = VectorStore::load(cache.path(), &mut embedder).unwrap();
+        let hits = store.search(&embedder, "cargo workspaces", 15);
+        assert!(hits[0].path.ends_with("rust.md"));
+        assert_eq!(hits[0].lines[0].number, 4);
+        assert_eq!(hits[0].lines[0].text, "# Cargo");
+
+        let hits = store.search(&embedder, "water the tomatoes", 15);
+        assert!(hits[0].path.ends_with("garden.md"));
+
+        // A store from another backend is ignored
+        assert!(VectorStore::load(cache.path(), &mut HashedTfIdf::new(16)).is_none());
+    }
+
+    #[test]
+    fn changed_and_deleted_notes_are_updated() {
+        let notes = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = notes.path();
+        std::fs::write(
+            root.join("rust.md"),
+            "# Rust
+the borrow checker
+",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("garden.md"),
+            "# Garden
+tomatoes and sun
+",
+        )
+        .unwrap();
+
+        let mut embedder = HashedTfIdf::new(DIM);
+        let mut store = VectorStore::build(root, &mut embedder, false);
+        assert!(!store.update(root, &embedder, false));
+
+        std::fs::remove_file(root.join("garden.md")).unwrap();
+        std::fs::write(
+            root.join("zucchini.md"),
+            "# Zucchini
+tomatoes and squash
+",
+        )
+        .unwrap();
+        assert!(store.update(root, &embedder, false));
+        store.save(cache.path(), &embedder).unwrap();
+
+        let store = VectorStore::load(cache.path(), &mut embedder).unwrap();
+        let paths: Vec<String> = store
+            .search(&embedder, "tomatoes", 15)
+            .into_iter()
+            .map(|h| h.path)
+            .collect();
+        assert_eq!(paths, vec![root.join("zucchini.md").display().to_string()]);
+    }
+}
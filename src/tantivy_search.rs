@@ -1,22 +1,151 @@
 use crate::config::Config;
-use duct::cmd;
+use crate::metadata;
+use crate::output::{self, Format, Record};
+use crate::semantic;
 
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::path::Path;
+use std::fmt;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, Value, FAST, INDEXED, STORED,
+    STRING, TEXT,
+};
+use tantivy::tokenizer::{LowerCaser, RawTokenizer, TextAnalyzer};
+use tantivy::{DateTime, Index, IndexWriter, TantivyDocument, Term};
 // TODO just use fd?
 use walkdir::WalkDir;
 
-pub fn run(config: Config, verbose: bool, reindex: bool, query: &String, init: bool) {
-    let _ = verbose;
-    let cache = get_cache(&config.note_taking_dir);
+/// A single search result
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub path: String,
+    pub title: Option<String>,
+    pub score: f32,
+    /// Lines of the note containing query terms
+    pub lines: Vec<MatchLine>,
+}
+
+/// A line of a note that matched the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchLine {
+    /// 1-based line number
+    pub number: usize,
+    pub text: String,
+    /// Byte ranges of the query terms within `text`
+    pub highlights: Vec<Range<usize>>,
+}
+
+impl SearchHit {
+    /// The first matching line becomes the snippet
+    pub fn to_record(&self) -> Record {
+        let first = self.lines.first();
+        Record {
+            path: self.path.clone(),
+            title: self.title.clone(),
+            score: Some(self.score),
+            snippet: first.map(|l| l.text.clone()),
+            line: first.map(|l| l.number),
+        }
+    }
+}
+
+/// Only show the first few matching lines of each note
+const MAX_LINES_PER_HIT: usize = 3;
+
+/// Tracks what is in the index so that only changed notes are re-indexed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: HashMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Modification time in nanoseconds since the epoch
+    pub mtime: u64,
+    /// sha256 of the content
+    pub hash: String,
+}
+
+impl Manifest {
+    fn path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join("nt_manifest.json")
+    }
+
+    /// `None` if the manifest is missing or unreadable
+    pub fn load(cache_dir: &Path) -> Option<Manifest> {
+        std::fs::read_to_string(Manifest::path(cache_dir))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    pub fn save(&self, cache_dir: &Path) -> std::io::Result<()> {
+        let serialized = serde_json::to_string(self).expect("Unable to serialize manifest");
+        std::fs::write(Manifest::path(cache_dir), serialized)
+    }
+}
+
+/// How many documents an indexing run touched
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexStats {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} deleted, {} unchanged",
+            self.added, self.updated, self.deleted, self.unchanged
+        )
+    }
+}
+
+/// Options shared by keyword, semantic and hybrid search
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub reindex: bool,
+    pub init: bool,
+    pub paths_only: bool,
+    /// Fuse keyword and semantic rankings
+    pub hybrid: bool,
+    /// Number of results
+    pub count: usize,
+    pub format: Format,
+}
+
+/// How many results of each ranking are fused, deeper than `count` so that
+/// notes ranked moderately by both can still make the cut
+const HYBRID_DEPTH: usize = 50;
+
+/// The usual constant for reciprocal rank fusion, dampens the top ranks
+const RRF_K: f32 = 60.0;
+
+pub fn run(
+    config: Config,
+    verbose: bool,
+    query: &str,
+    opts: &SearchOptions,
+) -> tantivy::Result<()> {
+    let (reindex, init) = (opts.reindex, opts.init);
+    let cache = get_cache(&config);
 
     if init {
         // remove the cache directory
         let _ = std::fs::remove_dir_all(&cache);
 
         // Re-initialize
-        create_tantivy(&cache);
+        create_tantivy(&cache)?;
     }
 
     if reindex {
@@ -25,27 +154,202 @@ pub fn run(config: Config, verbose: bool, reindex: bool, query: &String, init: b
             std::path::Path::new(&cache),
             std::path::Path::new(&config.note_taking_dir),
             4,
-        );
+            verbose,
+        )?;
+    }
+
+    ensure_index(&cache, &config, verbose)?;
+
+    let hits = if opts.hybrid {
+        let depth = opts.count.max(HYBRID_DEPTH);
+        let lexical = tantivy_search(query, &cache, depth)?;
+        let semantic = semantic::search(&config, verbose, reindex || init, query, depth)?;
+        let weights = (config.hybrid_lexical_weight, config.hybrid_semantic_weight);
+        fuse(lexical, semantic, weights, opts.count)
+    } else {
+        tantivy_search(query, &cache, opts.count)?
+    };
+    print_results(&hits, opts.paths_only, opts.format);
+    Ok(())
+}
+
+/// Create the index if it is missing or was built by an older version
+fn ensure_index(cache: &str, config: &Config, verbose: bool) -> tantivy::Result<()> {
+    // Indexes from older versions lack the newer fields
+    if Path::new(cache).exists() && !schema_is_current(cache) {
+        eprintln!("Index schema changed, rebuilding it...");
+        let _ = std::fs::remove_dir_all(cache);
     }
 
     //check if the cache exists
-    if !std::path::Path::new(&cache).exists() {
-        println!("Cache does not exist, creating it...");
-        let _ = std::fs::create_dir_all(&cache);
-        create_tantivy(&cache);
+    if !Path::new(cache).exists() {
+        eprintln!("Cache does not exist, creating it...");
+        create_tantivy(cache)?;
         index_tantivy(
-            std::path::Path::new(&cache),
-            std::path::Path::new(&config.note_taking_dir),
+            Path::new(cache),
+            Path::new(&config.note_taking_dir),
             4,
-        );
+            verbose,
+        )?;
     }
+    Ok(())
+}
 
-    tantivy_search(&query, &cache, 15);
+/// The best match for `query`, e.g. to open it
+pub fn best_hit(config: &Config, verbose: bool, query: &str) -> tantivy::Result<Option<SearchHit>> {
+    let cache = get_cache(config);
+    ensure_index(&cache, config, verbose)?;
+    Ok(tantivy_search(query, &cache, 1)?.into_iter().next())
 }
 
-fn get_cache(notes_dir: &str) -> String {
+/// A function returning the paths of the best `n` matches of a query, for the
+/// picker to search as you type. The index is created first, so that its
+/// messages don't garble the picker.
+pub fn live_search(
+    config: &Config,
+    verbose: bool,
+    n: usize,
+) -> tantivy::Result<impl FnMut(&str) -> Vec<String>> {
+    let cache = get_cache(config);
+    ensure_index(&cache, config, verbose)?;
+    Ok(move |query: &str| {
+        // Half typed queries may not parse
+        tantivy_search(query, &cache, n)
+            .map(|hits| hits.into_iter().map(|h| h.path).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// The notes containing any of the phrases, in no particular order.
+/// The index is brought up to date first, as stale notes would be missed.
+pub fn phrase_search(
+    config: &Config,
+    verbose: bool,
+    phrases: &[String],
+) -> tantivy::Result<Vec<PathBuf>> {
+    let cache = get_cache(config);
+    ensure_index(&cache, config, verbose)?;
+    index_tantivy(
+        Path::new(&cache),
+        Path::new(&config.note_taking_dir),
+        4,
+        verbose,
+    )?;
+
+    let index = open_index(&cache)?;
+    let fields = Fields::new(&index.schema())?;
+    let searcher = index.reader()?.searcher();
+    let parser = QueryParser::for_index(&index, vec![fields.content]);
+    let query: Vec<String> = phrases
+        .iter()
+        .map(|p| format!("\"{}\"", p.replace(['"', '\\'], " ")))
+        .collect();
+    let query = parser.parse_query(&query.join(" "))?;
+
+    let limit = (searcher.num_docs() as usize).max(1);
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+    top_docs
+        .into_iter()
+        .map(|(_, address)| {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let path = doc.get_first(fields.path).and_then(|v| v.as_str());
+            Ok(PathBuf::from(path.unwrap_or_default()))
+        })
+        .collect()
+}
+
+/// Weighted reciprocal rank fusion, each list adds `weight / (k + rank)`.
+///
+/// The keyword hit is kept where both lists have a note, its lines are highlighted.
+fn fuse(
+    lexical: Vec<SearchHit>,
+    semantic: Vec<SearchHit>,
+    (lexical_weight, semantic_weight): (f32, f32),
+    n: usize,
+) -> Vec<SearchHit> {
+    let mut fused: HashMap<String, SearchHit> = HashMap::new();
+    for (hits, weight) in [(lexical, lexical_weight), (semantic, semantic_weight)] {
+        for (rank, hit) in hits.into_iter().enumerate() {
+            let score = weight / (RRF_K + rank as f32 + 1.0);
+            fused
+                .entry(hit.path.clone())
+                .and_modify(|h| h.score += score)
+                .or_insert(SearchHit { score, ..hit });
+        }
+    }
+    let mut hits: Vec<SearchHit> = fused.into_values().collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.path.cmp(&b.path)));
+    hits.truncate(n);
+    hits
+}
+
+/// Print search results in the requested format
+pub fn print_results(hits: &[SearchHit], paths_only: bool, format: Format) {
+    match format {
+        Format::Plain if paths_only => {
+            for hit in hits {
+                println!("{}", hit.path);
+            }
+        }
+        Format::Plain => print_hits(hits, std::io::stdout().is_terminal()),
+        _ => {
+            let records: Vec<Record> = hits.iter().map(SearchHit::to_record).collect();
+            output::print_json(&records, format);
+        }
+    }
+}
+
+/// Print hits like `rg --heading`, the path and score followed by each matching line
+fn print_hits(hits: &[SearchHit], color: bool) {
+    for (i, hit) in hits.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        if color {
+            println!(
+                "\x1b[35m{}\x1b[0m  \x1b[2m{:.2}\x1b[0m",
+                hit.path, hit.score
+            );
+        } else {
+            println!("{}  {:.2}", hit.path, hit.score);
+        }
+        for line in &hit.lines {
+            let text = highlight(&line.text, &line.highlights, color);
+            if color {
+                println!("\x1b[32m{}\x1b[0m:{text}", line.number);
+            } else {
+                println!("{}:{text}", line.number);
+            }
+        }
+    }
+}
+
+/// Wrap the highlighted ranges in bold red, or leave the text alone without color
+fn highlight(text: &str, ranges: &[Range<usize>], color: bool) -> String {
+    if !color {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut last = 0;
+    for range in ranges {
+        out.push_str(&text[last..range.start]);
+        out.push_str("\x1b[1;31m");
+        out.push_str(&text[range.clone()]);
+        out.push_str("\x1b[0m");
+        last = range.end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// The index lives under the XDG cache directory, named vaults get their own
+/// directory so that their indexes never mix
+pub fn get_cache(config: &Config) -> String {
     if let Some(cache_dir) = dirs::cache_dir() {
-        println!("XDG Cache Directory: {}", cache_dir.display());
+        if let Some(vault) = &config.vault {
+            return format!("{}/rust_nt/vaults/{vault}/slipbox", cache_dir.display());
+        }
+        let notes_dir = &config.note_taking_dir;
         let home = format!("{}/", std::env::var("HOME").expect("HOME not set"));
         let notes_dir = notes_dir.replace(&home, "");
         format!("{}/rust_nt/{notes_dir}/slipbox", cache_dir.display())
@@ -54,114 +358,471 @@ fn get_cache(notes_dir: &str) -> String {
     }
 }
 
-fn create_tantivy(cache_dir: &str) {
-    println!("Creating Tantivy index in {}", cache_dir);
-    println!(
-        "Ensure that the Tantivy dictionary uses these same hardcoded fields: {:?}",
-        FIELDS
+/// The fields of the index
+///     path: the path to the file
+///     title: frontmatter `title` or the first H1
+///     heading: every heading
+///     tag: frontmatter `tags` and inline `#tags`, matched whole and case-insensitively
+///     modified: mtime of the file when its content last changed
+///     content: the content of the file
+///
+/// Queries can be scoped to a field, e.g. `title:rust tag:meeting`
+fn build_schema() -> Schema {
+    let tag_options = TextOptions::default().set_stored().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(TAG_TOKENIZER)
+            .set_index_option(IndexRecordOption::Basic),
     );
 
-    cmd!("tantivy", "new", "-i", cache_dir)
-        .run()
-        .expect("Unable to run tantivy-cli");
+    let mut schema_builder = Schema::builder();
+    schema_builder.add_text_field("path", STRING | STORED);
+    schema_builder.add_text_field("title", TEXT | STORED);
+    schema_builder.add_text_field("heading", TEXT);
+    schema_builder.add_text_field("tag", tag_options);
+    schema_builder.add_date_field("modified", INDEXED | STORED | FAST);
+    schema_builder.add_text_field("content", TEXT);
+    schema_builder.build()
 }
 
-fn index_tantivy(cache_dir: &Path, notes_dir: &Path, threads: u32) {
-    // Create a jsonlines file
-    let jsonlines = format!(
-        "{}/slipbox.jsonl",
-        cache_dir
-            .to_str()
-            .unwrap_or_else(|| panic!("Unable to convert cache_dir to string"))
-    );
-    let jsonlines = Path::new(&jsonlines);
-    make_jsonlines(notes_dir, jsonlines);
-
-    cmd!("cat", jsonlines)
-        .pipe(cmd!(
-            "tantivy",
-            "index",
-            "-i",
-            cache_dir,
-            "-t",
-            threads.to_string()
-        ))
-        .run()
-        .expect("Unable to run tantivy-cli");
-}
-
-// TODO should probably deal with json in here
-fn tantivy_search(query: &str, cache_dir: &str, n: u32) {
-    cmd!("tantivy", "search", "-i", cache_dir, "--query", query)
-        .pipe(cmd!("jq", ".path[]"))
-        .pipe(cmd!("tr", "-d", "\""))
-        .pipe(cmd!("tac"))
-        .pipe(cmd!("tail", "-n", n.to_string()))
-        .run()
-        .expect("Unable to run tantivy-cli for the search");
-}
-
-// Create a jsonlines file
-
-/// This uses to fields
-///     path: the path to the file
-///     content: the content of the file
-/// Ensure that the Tantivy dictionary uses these same hardcoded fields
-fn make_jsonlines(dir_path: &Path, output_path: &Path) {
+/// Tags are matched whole, so `project/nt` stays one token
+const TAG_TOKENIZER: &str = "tag";
+
+struct Fields {
+    path: Field,
+    title: Field,
+    heading: Field,
+    tag: Field,
+    modified: Field,
+    content: Field,
+}
+
+impl Fields {
+    fn new(schema: &Schema) -> tantivy::Result<Fields> {
+        Ok(Fields {
+            path: schema.get_field("path")?,
+            title: schema.get_field("title")?,
+            heading: schema.get_field("heading")?,
+            tag: schema.get_field("tag")?,
+            modified: schema.get_field("modified")?,
+            content: schema.get_field("content")?,
+        })
+    }
+
+    fn document(&self, path: &str, body: &str, mtime: u64) -> TantivyDocument {
+        let meta = metadata::parse(body);
+        let mut doc = TantivyDocument::default();
+        doc.add_text(self.path, path);
+        if let Some(title) = &meta.title {
+            doc.add_text(self.title, title);
+        }
+        for heading in &meta.headings {
+            doc.add_text(self.heading, heading);
+        }
+        for tag in &meta.tags {
+            doc.add_text(self.tag, tag);
+        }
+        doc.add_date(self.modified, DateTime::from_timestamp_nanos(mtime as i64));
+        doc.add_text(self.content, body);
+        doc
+    }
+}
+
+/// Open an index and register the custom tokenizers
+fn open_index(cache_dir: impl AsRef<Path>) -> tantivy::Result<Index> {
+    let index = Index::open_in_dir(cache_dir)?;
+    register_tokenizers(&index);
+    Ok(index)
+}
 
-    // Get all markdown files
-    let md_files: Vec<_> = WalkDir::new(dir_path)
+fn register_tokenizers(index: &Index) {
+    let tag = TextAnalyzer::builder(RawTokenizer::default())
+        .filter(LowerCaser)
+        .build();
+    index.tokenizers().register(TAG_TOKENIZER, tag);
+}
+
+fn schema_is_current(cache_dir: &str) -> bool {
+    Index::open_in_dir(cache_dir).is_ok_and(|index| index.schema() == build_schema())
+}
+
+fn create_tantivy(cache_dir: &str) -> tantivy::Result<Index> {
+    eprintln!("Creating Tantivy index in {}", cache_dir);
+    std::fs::create_dir_all(cache_dir)?;
+    let index = Index::create_in_dir(cache_dir, build_schema())?;
+    register_tokenizers(&index);
+    Ok(index)
+}
+
+/// Re-index after notes were changed by nt itself. A missing or outdated index
+/// is left for the next search to build.
+pub fn update_index(config: &Config, verbose: bool) -> tantivy::Result<()> {
+    let cache = get_cache(config);
+    if !Path::new(&cache).exists() || !schema_is_current(&cache) {
+        return Ok(());
+    }
+    index_tantivy(
+        Path::new(&cache),
+        Path::new(&config.note_taking_dir),
+        4,
+        verbose,
+    )?;
+    Ok(())
+}
+
+/// Bring the index up to date with the notes directory.
+///
+/// Files whose mtime is unchanged are skipped without being read, files whose
+/// mtime changed are hashed and only re-indexed if the content differs.
+fn index_tantivy(
+    cache_dir: &Path,
+    notes_dir: &Path,
+    threads: usize,
+    verbose: bool,
+) -> tantivy::Result<IndexStats> {
+    let index = open_index(cache_dir)?;
+    let fields = Fields::new(&index.schema())?;
+
+    let mut manifest = Manifest::default();
+    let mut stats = IndexStats::default();
+
+    // 50MB per thread is plenty for a slipbox
+    let mut writer: IndexWriter = index.writer_with_num_threads(threads, threads * 50_000_000)?;
+
+    // Without a manifest we can't know what is in the index, so start over
+    let old_manifest = match Manifest::load(cache_dir) {
+        Some(m) => m,
+        None => {
+            writer.delete_all_documents()?;
+            Manifest::default()
+        }
+    };
+
+    for file in note_paths(notes_dir) {
+        let key = file.display().to_string();
+        let Some(mtime) = mtime(&file) else {
+            continue;
+        };
+        let old = old_manifest.files.get(&key);
+        if let Some(old) = old.filter(|e| e.mtime == mtime) {
+            manifest.files.insert(key, old.clone());
+            stats.unchanged += 1;
+            continue;
+        }
+
+        let Ok(body) = std::fs::read_to_string(&file) else {
+            if verbose {
+                eprintln!("Failed to read {key}");
+            }
+            continue;
+        };
+        let hash = hash(&body);
+        let changed = match old {
+            Some(old) if old.hash == hash => {
+                stats.unchanged += 1;
+                false
+            }
+            Some(_) => {
+                writer.delete_term(Term::from_field_text(fields.path, &key));
+                stats.updated += 1;
+                true
+            }
+            None => {
+                stats.added += 1;
+                true
+            }
+        };
+        if changed {
+            if verbose {
+                print_note(&key, &body);
+            }
+            writer.add_document(fields.document(&key, &body, mtime))?;
+        }
+        manifest.files.insert(key, ManifestEntry { mtime, hash });
+    }
+
+    for key in old_manifest.files.keys() {
+        if !manifest.files.contains_key(key) {
+            writer.delete_term(Term::from_field_text(fields.path, key));
+            stats.deleted += 1;
+        }
+    }
+
+    writer.commit()?;
+    manifest.save(cache_dir)?;
+    eprintln!("Indexed: {stats}");
+    Ok(stats)
+}
+
+fn tantivy_search(query: &str, cache_dir: &str, n: usize) -> tantivy::Result<Vec<SearchHit>> {
+    let index = open_index(cache_dir)?;
+    let fields = Fields::new(&index.schema())?;
+
+    let reader = index.reader()?;
+    let searcher = reader.searcher();
+    let mut parser =
+        QueryParser::for_index(&index, vec![fields.title, fields.heading, fields.content]);
+    parser.set_field_boost(fields.title, 2.0);
+    let query = parser.parse_query(query)?;
+
+    // The terms to highlight, tag and date queries don't appear verbatim in the text
+    let mut terms = HashSet::new();
+    query.query_terms(&mut |term, _| {
+        let text_field = [fields.title, fields.heading, fields.content].contains(&term.field());
+        if let (true, Some(text)) = (text_field, term.value().as_str()) {
+            terms.insert(text.to_string());
+        }
+    });
+    let mut analyzer = index.tokenizer_for_field(fields.content)?;
+
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(n))?;
+    top_docs
+        .into_iter()
+        .map(|(score, address)| {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let path = doc
+                .get_first(fields.path)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let title = doc
+                .get_first(fields.title)
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            let lines = std::fs::read_to_string(&path)
+                .map(|content| matching_lines(&content, &terms, &mut analyzer))
+                .unwrap_or_default();
+            Ok(SearchHit {
+                path,
+                title,
+                score,
+                lines,
+            })
+        })
+        .collect()
+}
+
+/// Find the lines containing any of the terms, tokenizing each line the same
+/// way the content was indexed so that the terms compare equal
+fn matching_lines(
+    content: &str,
+    terms: &HashSet<String>,
+    analyzer: &mut TextAnalyzer,
+) -> Vec<MatchLine> {
+    if terms.is_empty() {
+        return vec![];
+    }
+    let mut lines = vec![];
+    for (i, text) in content.lines().enumerate() {
+        let mut highlights = vec![];
+        let mut stream = analyzer.token_stream(text);
+        while let Some(token) = stream.next() {
+            if terms.contains(&token.text) {
+                highlights.push(token.offset_from..token.offset_to);
+            }
+        }
+        if !highlights.is_empty() {
+            lines.push(MatchLine {
+                number: i + 1,
+                text: text.to_string(),
+                highlights,
+            });
+            if lines.len() == MAX_LINES_PER_HIT {
+                break;
+            }
+        }
+    }
+    lines
+}
+
+/// Get the paths of all markdown files, skipping hidden directories like `.templates`
+pub fn note_paths(dir_path: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir_path)
         .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
         // Filter for valid entries
         .filter_map(|entry| entry.ok())
         // Filter for md files
         .filter(|entry| entry.path().extension() == Some(OsStr::new("md")))
-        // Convert to string
-        .map(|entry| entry.path().display().to_string())
-        .collect();
+        .map(|entry| entry.into_path())
+        .collect()
+}
 
-    // Build a dictionary
-    let d_list: Vec<HashMap<String, String>> = md_files
-        .iter()
-        .filter_map(|file| {
-            let path = file.to_string();
-            let content_result = std::fs::read_to_string(file);
-            match content_result {
-                Ok(content) => Some(HashMap::from([
-                    ("path".to_string(), path),
-                    ("content".to_string(), content),
-                ])),
-                Err(_) => None,
+/// Modification time in nanoseconds since the epoch
+pub fn mtime(file: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+pub fn hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+fn print_note(path: &str, body: &str) {
+    let body: String = body.chars().take(10).collect();
+    let body = body.replace('\n', r#"  \n  "#);
+    eprintln!("{path:<60}:\t{body}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_and_search_in_process() {
+        let notes = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_dir = cache.path().join("slipbox");
+        let cache_dir = cache_dir.to_str().unwrap();
+
+        std::fs::write(
+            notes.path().join("rust.md"),
+            "# Rust\nborrow the borrow checker",
+        )
+        .unwrap();
+        std::fs::write(
+            notes.path().join("python.md"),
+            "# Python\ngarbage collector",
+        )
+        .unwrap();
+        std::fs::write(notes.path().join("ignored.txt"), "borrow checker").unwrap();
+
+        create_tantivy(cache_dir).unwrap();
+        index_tantivy(Path::new(cache_dir), notes.path(), 1, false).unwrap();
+
+        let hits = tantivy_search("borrow", cache_dir, 15).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("rust.md"));
+        assert!(hits[0].score > 0.0);
+        assert_eq!(
+            hits[0].lines,
+            vec![MatchLine {
+                number: 2,
+                text: "borrow the borrow checker".to_string(),
+                highlights: vec![0..6, 11..17],
+            }]
+        );
+
+        // Reindexing replaces rather than duplicates documents
+        index_tantivy(Path::new(cache_dir), notes.path(), 1, false).unwrap();
+        assert_eq!(tantivy_search("borrow", cache_dir, 15).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reindex_only_touches_changed_notes() {
+        let notes = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_dir = cache.path().join("slipbox");
+        let cache_str = cache_dir.to_str().unwrap();
+
+        std::fs::write(notes.path().join("a.md"), "alpha").unwrap();
+        std::fs::write(notes.path().join("b.md"), "beta").unwrap();
+        std::fs::write(notes.path().join("c.md"), "gamma").unwrap();
+
+        create_tantivy(cache_str).unwrap();
+        let stats = index_tantivy(&cache_dir, notes.path(), 1, false).unwrap();
+        assert_eq!(stats.added, 3);
+
+        let stats = index_tantivy(&cache_dir, notes.path(), 1, false).unwrap();
+        assert_eq!(
+            stats,
+            IndexStats {
+                unchanged: 3,
+                ..IndexStats::default()
             }
-        })
-        .collect();
+        );
 
-    // Print the dictionary
-    print_dict(&d_list);
+        // Pretend every mtime changed, b's content is the same so it is only hashed
+        let mut manifest = Manifest::load(&cache_dir).unwrap();
+        for entry in manifest.files.values_mut() {
+            entry.mtime = 0;
+        }
+        manifest.save(&cache_dir).unwrap();
+        std::fs::write(notes.path().join("a.md"), "delta").unwrap();
+        std::fs::remove_file(notes.path().join("c.md")).unwrap();
+        std::fs::write(notes.path().join("d.md"), "epsilon").unwrap();
 
-    // Write the dictionary to a jsonlines file
-    let serialized = d_list
-        .iter()
-        .map(|d| serde_json::to_string(d).unwrap())
-        .collect::<Vec<String>>()
-        .join("\n");
+        let stats = index_tantivy(&cache_dir, notes.path(), 1, false).unwrap();
+        assert_eq!(
+            stats,
+            IndexStats {
+                added: 1,
+                updated: 1,
+                deleted: 1,
+                unchanged: 1,
+            }
+        );
+        assert!(tantivy_search("alpha", cache_str, 15).unwrap().is_empty());
+        assert_eq!(tantivy_search("delta", cache_str, 15).unwrap().len(), 1);
+        assert!(tantivy_search("gamma", cache_str, 15).unwrap().is_empty());
+        assert_eq!(tantivy_search("beta", cache_str, 15).unwrap().len(), 1);
+    }
 
-    std::fs::write(output_path, serialized).expect("Unable to write file");
-}
+    #[test]
+    fn field_scoped_queries() {
+        let notes = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let cache_dir = cache.path().join("slipbox");
+        let cache_str = cache_dir.to_str().unwrap();
 
-fn print_dict(d_list: &Vec<HashMap<String, String>>) {
-    d_list.iter().for_each(|d| {
-        // NOTE expect is safe here because we know the keys are in the dict
-        let body = d.get("content").expect("Missing content in dict");
-        let body = if body.len() > 10 { &body[0..10] } else { &body };
-        let body = body.replace("\n", r#"  \n  "#);
+        std::fs::write(
+            notes.path().join("standup.md"),
+            "---\ntags: [Meeting]\n---\n# Rust standup\n## Actions\n",
+        )
+        .unwrap();
+        std::fs::write(
+            notes.path().join("book.md"),
+            "# Programming\nA meeting about rust #reading",
+        )
+        .unwrap();
 
-        let path = d.get("path").expect("Missing path in dict");
-        let path = path.replace("/home/ryan/Notes/slipbox/", "");
+        create_tantivy(cache_str).unwrap();
+        index_tantivy(&cache_dir, notes.path(), 1, false).unwrap();
 
-        println!("{path:<60}:\t{body}")
-    });
-}
+        let paths = |q: &str| -> Vec<String> {
+            tantivy_search(q, cache_str, 15)
+                .unwrap()
+                .into_iter()
+                .map(|h| h.path.rsplit('/').next().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(paths("title:rust"), vec!["standup.md"]);
+        assert_eq!(paths("tag:meeting"), vec!["standup.md"]);
+        assert_eq!(paths("title:rust tag:MEETING"), vec!["standup.md"]);
+        assert_eq!(paths("heading:actions"), vec!["standup.md"]);
+        assert_eq!(paths("tag:reading"), vec!["book.md"]);
+        assert_eq!(paths("modified:[2000-01-01T00:00:00Z TO *]").len(), 2);
+    }
+
+    #[test]
+    fn fusion_rewards_notes_ranked_by_both() {
+        let hit = |path: &str| SearchHit {
+            path: path.to_string(),
+            title: None,
+            score: 0.0,
+            lines: vec![],
+        };
+        let lexical = vec![hit("a"), hit("b"), hit("c")];
+        let semantic = vec![hit("d"), hit("b"), hit("a")];
 
-// Constant for the fields
-const FIELDS: [&str; 2] = ["path", "content"];
+        let paths = |hits: Vec<SearchHit>| hits.into_iter().map(|h| h.path).collect::<Vec<_>>();
+        assert_eq!(
+            paths(fuse(lexical.clone(), semantic.clone(), (1.0, 1.0), 2)),
+            vec!["a", "b"]
+        );
+        // Ignoring the semantic ranking gives back the keyword ranking
+        assert_eq!(
+            paths(fuse(lexical, semantic, (1.0, 0.0), 4)),
+            vec!["a", "b", "c", "d"]
+        );
+    }
+
+    #[test]
+    fn highlight_wraps_ranges() {
+        let ranges = [2..8, 9..10];
+        assert_eq!(highlight("a borrow b", &ranges, false), "a borrow b");
+        assert_eq!(
+            highlight("a borrow b", &ranges, true),
+            "a \x1b[1;31mborrow\x1b[0m \x1b[1;31mb\x1b[0m"
+        );
+    }
+}
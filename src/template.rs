@@ -0,0 +1,338 @@
+use crate::config::{self, Config};
+use crate::new;
+use crate::output::{self, Format, Record};
+use chrono::{DateTime, Duration, Local, Months};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Used by `nt new` when no template is configured
+pub const DEFAULT_NOTE: &str = r#"---
+id: {{id}}
+title: {{title:yaml}}
+date: {{date}}
+author: {{author:yaml}}
+tags: [{{tags}}]
+---
+
+# {{title}}
+
+"#;
+
+// `{{ name }}`, `{{ date+7d:%A }}` or `{{ name|default }}`
+static VARIABLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{\s*(\w+(?:-[a-zA-Z_]\w*)*)\s*(?:([+-])\s*(\d+)\s*([hdwmy]))?(?::([^|}]*?))?\s*(?:\|([^}]*?))?\s*\}\}")
+        .expect("Failed to create regex")
+});
+
+/// A named template, the file stem of a file in a templates directory
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// The directories holding templates, the vault's `.templates` then
+/// `$XDG_CONFIG_HOME/nt/templates`, or only `templates_dir` if it is set
+pub fn dirs(config: &Config) -> Vec<PathBuf> {
+    if let Some(dir) = &config.templates_dir {
+        return vec![PathBuf::from(dir)];
+    }
+    let mut dirs = vec![Path::new(&config.note_taking_dir).join(".templates")];
+    dirs.extend(config::default_path().and_then(|p| Some(p.parent()?.join("templates"))));
+    dirs
+}
+
+/// The templates in `dirs`, sorted by name, a name in an earlier directory
+/// hides the same name in later ones
+pub fn templates(dirs: &[PathBuf]) -> Vec<Template> {
+    let mut templates: Vec<Template> = vec![];
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect();
+        paths.sort();
+        for path in paths {
+            let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            if name.starts_with('.') || templates.iter().any(|t| t.name == name) {
+                continue;
+            }
+            templates.push(Template { name, path });
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    templates
+}
+
+/// Read the template named `name`, or at the path `name` if it is a file
+pub fn load(config: &Config, name: &str) -> io::Result<String> {
+    let path = Path::new(name);
+    let path = match path.is_file() {
+        true => path.to_path_buf(),
+        false => {
+            let dirs = dirs(config);
+            templates(&dirs)
+                .into_iter()
+                .find(|t| t.name == name)
+                .map(|t| t.path)
+                .ok_or_else(|| {
+                    let dirs: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No template named {name:?} in {}", dirs.join(", ")),
+                    )
+                })?
+        }
+    };
+    fs::read_to_string(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+/// The variables every template has, besides `date` and `time`
+pub fn variables(config: &Config, now: &DateTime<Local>) -> HashMap<String, String> {
+    let author = config
+        .author
+        .clone()
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_default();
+    HashMap::from([
+        ("id".to_string(), new::id(now)),
+        ("author".to_string(), author),
+    ])
+}
+
+/// Substitute each `{{name}}` with its value.
+///
+/// `{{date}}` and `{{time}}` are `now`, shifted by an offset like `{{date+7d}}`
+/// or `{{time-2h}}` (`h`ours, `d`ays, `w`eeks, `m`onths or `y`ears) and
+/// formatted with a strftime format like `{{date:%A}}`. Empty or missing
+/// variables take the default in `{{name|default}}`, else `ask` is asked for
+/// them once, an unanswered variable is left empty. `{{name:yaml}}` is the
+/// value as a quoted YAML string, for frontmatter.
+pub fn render(
+    template: &str,
+    vars: &HashMap<String, String>,
+    now: &DateTime<Local>,
+    mut ask: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut answers: HashMap<String, String> = HashMap::new();
+    VARIABLE
+        .replace_all(template, |caps: &regex::Captures| {
+            let name = &caps[1];
+            let format = caps.get(5).map(|m| m.as_str().trim());
+            let default = caps.get(6).map(|m| m.as_str().trim_start());
+            let offset = caps.get(2).map(|sign| {
+                let n: u32 = caps[3].parse().unwrap_or(u32::MAX);
+                (sign.as_str() == "-", n, &caps[4])
+            });
+            let date_format = match name {
+                "date" => Some("%Y-%m-%d"),
+                "time" => Some("%H:%M"),
+                _ => None,
+            };
+            if let Some(date_format) = date_format {
+                let date = match offset {
+                    Some((back, n, unit)) => shift(now, back, n, unit),
+                    None => Some(*now),
+                };
+                let mut text = String::new();
+                return match date
+                    .map(|d| write!(text, "{}", d.format(format.unwrap_or(date_format))))
+                {
+                    Some(Ok(())) => text,
+                    // An invalid format or a date out of range
+                    _ => caps[0].to_string(),
+                };
+            }
+            if offset.is_some() || format.is_some_and(|f| f != "yaml") {
+                // Only dates can be shifted and formatted
+                return caps[0].to_string();
+            }
+            let value = match (vars.get(name).filter(|v| !v.is_empty()), default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.to_string(),
+                (None, None) if vars.contains_key(name) => String::new(),
+                (None, None) => answers
+                    .entry(name.to_string())
+                    .or_insert_with(|| ask(name).unwrap_or_default())
+                    .clone(),
+            };
+            match format {
+                Some(_) => yaml_quote(&value),
+                None => value,
+            }
+        })
+        .into_owned()
+}
+
+/// `value` as a double-quoted YAML scalar
+pub fn yaml_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `now` moved `n` units forward, or back
+fn shift(now: &DateTime<Local>, back: bool, n: u32, unit: &str) -> Option<DateTime<Local>> {
+    let duration = match unit {
+        "h" => Some(Duration::try_hours(n.into())?),
+        "d" => Some(Duration::try_days(n.into())?),
+        "w" => Some(Duration::try_weeks(n.into())?),
+        _ => None,
+    };
+    let months = match unit {
+        "m" => n,
+        "y" => n.checked_mul(12)?,
+        _ => 0,
+    };
+    match (duration, back) {
+        (Some(d), false) => now.checked_add_signed(d),
+        (Some(d), true) => now.checked_sub_signed(d),
+        (None, false) => now.checked_add_months(Months::new(months)),
+        (None, true) => now.checked_sub_months(Months::new(months)),
+    }
+}
+
+/// Ask for a variable on stderr and read the answer from stdin
+pub fn ask_stdin(name: &str) -> Option<String> {
+    eprint!("{name}: ");
+    io::stderr().flush().ok()?;
+    let answer = io::stdin().lock().lines().next()?.ok()?;
+    Some(answer.trim_end().to_string())
+}
+
+/// Parse a `KEY=VALUE` argument
+pub fn parse_var(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .ok_or_else(|| format!("expected KEY=VALUE, got {arg:?}"))
+}
+
+/// Print the name and path of each template
+pub fn list(config: Config, format: Format) {
+    let templates = templates(&dirs(&config));
+    if format == Format::Plain {
+        let width = templates.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        for t in &templates {
+            println!("{:width$}  {}", t.name, t.path.display());
+        }
+        return;
+    }
+    let records: Vec<Record> = templates
+        .into_iter()
+        .map(|t| {
+            let mut record = Record::new(t.path.display().to_string());
+            record.title = Some(t.name);
+            record
+        })
+        .collect();
+    output::print_json(&records, format);
+}
+
+/// Print the template named `name` as `nt new` would fill it in
+pub fn preview(config: Config, name: &str, vars: &[(String, String)]) -> io::Result<()> {
+    let template = load(&config, name)?;
+    let now = Local::now();
+    let mut variables = variables(&config, &now);
+    variables.extend(vars.iter().cloned());
+    if let Some(slug) = variables.get("title").map(|t| new::slug(t)) {
+        variables.entry("slug".to_string()).or_insert(slug);
+    }
+    print!("{}", render(&template, &variables, &now, ask_stdin));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn variables_are_substituted() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let vars = HashMap::from([
+            ("title".to_string(), "Rust".to_string()),
+            ("tags".to_string(), String::new()),
+        ]);
+        let mut asked = vec![];
+        let mut ask = |name: &str| {
+            asked.push(name.to_string());
+            (name == "project").then(|| "nt".to_string())
+        };
+        assert_eq!(
+            render(
+                "# {{title}} {{ title }} {{missing}}! {{tags}}/{{tags|inbox}}/{{x | a b}} \
+                 {{project}} {{project}}",
+                &vars,
+                &now,
+                &mut ask
+            ),
+            "# Rust Rust ! /inbox/a b nt nt"
+        );
+        assert_eq!(asked, vec!["missing", "project"]);
+    }
+
+    #[test]
+    fn dates_are_shifted_and_formatted() {
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 9, 30, 5).unwrap();
+        let render = |t| render(t, &HashMap::new(), &now, |_| None);
+        assert_eq!(render("{{date}} {{time}}"), "2024-01-31 09:30");
+        assert_eq!(render("{{date+7d}} {{date - 1w}}"), "2024-02-07 2024-01-24");
+        assert_eq!(render("{{date+1m}} {{date-1y}}"), "2024-02-29 2023-01-31");
+        assert_eq!(
+            render("{{time+2h}} {{date+1d:%A %d %B}}"),
+            "11:30 Thursday 01 February"
+        );
+        assert_eq!(
+            render("{{date:%Q}} {{title+1d}}"),
+            "{{date:%Q}} {{title+1d}}"
+        );
+    }
+
+    #[test]
+    fn earlier_directories_win() {
+        let vault = tempfile::tempdir().unwrap();
+        let global = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("meeting.md"), "vault").unwrap();
+        fs::write(global.path().join("meeting.md"), "global").unwrap();
+        fs::write(global.path().join("daily.org"), "").unwrap();
+        let dirs = [vault.path().to_path_buf(), global.path().to_path_buf()];
+        assert_eq!(
+            templates(&dirs),
+            vec![
+                Template {
+                    name: "daily".to_string(),
+                    path: global.path().join("daily.org"),
+                },
+                Template {
+                    name: "meeting".to_string(),
+                    path: vault.path().join("meeting.md"),
+                },
+            ]
+        );
+        assert_eq!(parse_var("a=b=c"), Ok(("a".to_string(), "b=c".to_string())));
+        assert!(parse_var("a").is_err());
+    }
+}